@@ -0,0 +1,299 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use gdal::Dataset;
+
+use crate::StitchError;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// on-disk cache of downloaded tiles, evicting least recently used
+/// entries once the directory exceeds its size limit
+#[derive(Debug)]
+pub struct TileCache {
+    directory: PathBuf,
+    index: Mutex<CacheIndex>,
+    max_size: u64,
+    refresh: bool,
+}
+
+/// size and access order of cached entries, so puts need not rescan
+/// the directory
+#[derive(Debug, Default)]
+struct CacheIndex {
+    /// entry path to its (access tick, length)
+    entries: HashMap<PathBuf, (u64, u64)>,
+    /// access tick to entry path, least recently used first
+    order: BTreeMap<u64, PathBuf>,
+    size: u64,
+    tick: u64,
+}
+
+impl CacheIndex {
+    /// record an access of path, returning the new access tick
+    fn touch(&mut self, path: &Path, len: Option<u64>) -> u64 {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let previous = self.entries.get(path).copied();
+        if let Some((previous_tick, previous_len)) = previous {
+            self.order.remove(&previous_tick);
+            self.size -= previous_len;
+        }
+
+        let len = match (len, previous) {
+            (Some(len), _) => len,
+            (None, Some((_, len))) => len,
+            (None, None) => 0,
+        };
+
+        self.entries.insert(path.to_path_buf(), (tick, len));
+        self.order.insert(tick, path.to_path_buf());
+        self.size += len;
+        tick
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some((tick, len)) = self.entries.remove(path) {
+            self.order.remove(&tick);
+            self.size -= len;
+        }
+    }
+
+    /// remove least recently used entries until size fits, returning
+    /// their paths
+    fn evict(&mut self, max_size: u64) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        while self.size > max_size {
+            let path = match self.order.values().next() {
+                Some(path) => path.clone(),
+                None => break,
+            };
+
+            self.remove(&path);
+            paths.push(path);
+        }
+
+        paths
+    }
+}
+
+impl TileCache {
+    /// open a cache directory, indexing existing entries by their last
+    /// access and removing writes interrupted by a previous run
+    pub fn new(directory: PathBuf, max_size: u64, refresh: bool)
+            -> Result<TileCache, StitchError> {
+        std::fs::create_dir_all(&directory)?;
+
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+            let metadata = std::fs::metadata(&path)?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            match path.extension().and_then(|x| x.to_str()) {
+                Some("tile") => entries.push((metadata.modified()?,
+                    metadata.len(), path)),
+                Some("tmp") => std::fs::remove_file(&path)?,
+                _ => {},
+            }
+        }
+
+        let mut index = CacheIndex::default();
+        entries.sort_by_key(|x| x.0);
+        for (_, len, path) in entries {
+            index.touch(&path, Some(len));
+        }
+
+        Ok(TileCache {
+            directory,
+            index: Mutex::new(index),
+            max_size,
+            refresh,
+        })
+    }
+
+    /// retrieve the datasets stored under key, if present
+    pub async fn get(&self, key: &str)
+            -> Result<Option<Vec<Dataset>>, StitchError> {
+        let path = self.path(key);
+        if self.refresh
+                || !self.index.lock().unwrap().entries.contains_key(&path) {
+            return Ok(None);
+        }
+
+        let buf = match tokio::fs::read(&path).await {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.index.lock().unwrap().remove(&path);
+                return Ok(None);
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        // hashes may collide, so verify the stored key
+        let mut reader = Cursor::new(buf);
+        if read_key(&mut reader)? != key {
+            return Ok(None);
+        }
+
+        let count = reader.read_u32::<BigEndian>()?;
+        let mut datasets = Vec::new();
+        for _ in 0..count {
+            let dataset = st_image::serialize::read(&mut reader)
+                .map_err(|e| StitchError::Deserialize(e.to_string()))?;
+            datasets.push(dataset);
+        }
+
+        // record access for lru eviction, on disk for later runs
+        self.index.lock().unwrap().touch(&path, None);
+        let result = tokio::task::spawn_blocking(move ||
+                OpenOptions::new().write(true).open(&path)?
+                    .set_modified(SystemTime::now()))
+            .await.map_err(|e| StitchError::Internal(e.to_string()))?;
+        result?;
+
+        Ok(Some(datasets))
+    }
+
+    /// store datasets under key, evicting entries to respect size limit
+    pub async fn put(&self, key: &str, datasets: &[Dataset])
+            -> Result<(), StitchError> {
+        let mut buf = Vec::new();
+        buf.write_u32::<BigEndian>(key.len() as u32)?;
+        buf.write_all(key.as_bytes())?;
+
+        buf.write_u32::<BigEndian>(datasets.len() as u32)?;
+        for dataset in datasets.iter() {
+            st_image::serialize::write(dataset, &mut buf)
+                .map_err(|e| StitchError::Internal(format!(
+                    "failed to serialize dataset: {}", e)))?;
+        }
+
+        // write to a temporary file so readers never see partial
+        // entries, named uniquely so concurrent puts do not collide
+        let path = self.path(key);
+        let tick = {
+            let mut index = self.index.lock().unwrap();
+            index.tick += 1;
+            index.tick
+        };
+        let tmp_path = path.with_extension(format!("{}.tmp", tick));
+        tokio::fs::write(&tmp_path, &buf).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.touch(&path, Some(buf.len() as u64));
+            index.evict(self.max_size)
+        };
+
+        for path in evicted {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound =>
+                    return Err(e.into()),
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let hash = yogi::fnv1a(key.as_bytes());
+        self.directory.join(format!("{:016x}.tile", hash))
+    }
+}
+
+fn read_key<T: Read>(reader: &mut T) -> Result<String, StitchError> {
+    let len = reader.read_u32::<BigEndian>()?;
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TileCache;
+
+    use std::path::PathBuf;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "stitch-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn files(directory: &PathBuf) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(directory).unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn key_stability() {
+        let directory = directory("key");
+        let cache = TileCache::new(directory.clone(), 1024, false).unwrap();
+
+        // entry names are 64-bit fnv-1a hashes of their keys
+        assert_eq!(cache.path("a"), directory.join("af63dc4c8601ec8c.tile"));
+        assert_eq!(cache.path("stip:/data/0.tif:9xj3e"),
+            cache.path("stip:/data/0.tif:9xj3e"));
+        assert_ne!(cache.path("stip:/data/0.tif:9xj3e"),
+            cache.path("stip:/data/0.tif:9xj3f"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_writes_through_temporary_file() {
+        let directory = directory("put");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("0123456789abcdef.1.tmp"), b"partial")
+            .unwrap();
+
+        // interrupted writes are removed when the cache is opened
+        let cache = TileCache::new(directory.clone(), 1024, false).unwrap();
+        assert!(files(&directory).is_empty());
+
+        cache.put("a", &[]).await.unwrap();
+        assert_eq!(files(&directory), vec!["af63dc4c8601ec8c.tile"]);
+        assert_eq!(cache.get("a").await.unwrap().map(|x| x.len()),
+            Some(0));
+        assert!(cache.get("b").await.unwrap().is_none());
+
+        // refreshing caches ignore existing entries
+        let cache = TileCache::new(directory.clone(), 1024, true).unwrap();
+        assert!(cache.get("a").await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        // each entry holds a 4 byte key length, 2 byte key, and 4 byte
+        // dataset count
+        let directory = directory("lru");
+        let cache = TileCache::new(directory.clone(), 25, false).unwrap();
+
+        cache.put("k1", &[]).await.unwrap();
+        cache.put("k2", &[]).await.unwrap();
+        assert!(cache.get("k1").await.unwrap().is_some());
+
+        cache.put("k3", &[]).await.unwrap();
+        assert!(cache.get("k1").await.unwrap().is_some());
+        assert!(cache.get("k2").await.unwrap().is_none());
+        assert!(cache.get("k3").await.unwrap().is_some());
+        assert_eq!(files(&directory).len(), 2);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
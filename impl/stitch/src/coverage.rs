@@ -0,0 +1,71 @@
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coverage {
+    /// tile downloaded directly from stip
+    Stip,
+    /// tile imputed by a stitchd server
+    Imputed,
+    /// tile unavailable, filled with no-data values
+    Empty,
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Coverage::Stip => write!(f, "stip"),
+            Coverage::Imputed => write!(f, "imputed"),
+            Coverage::Empty => write!(f, "empty"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CoverageEntry {
+    pub geohash: String,
+    pub timestamp: i64,
+    pub coverage: Coverage,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CoverageReport {
+    pub entries: Vec<CoverageEntry>,
+}
+
+impl CoverageReport {
+    pub fn push(&mut self, geohash: &str, timestamp: i64,
+            coverage: Coverage, message: Option<String>) {
+        self.entries.push(CoverageEntry {
+            geohash: geohash.to_string(),
+            timestamp,
+            coverage,
+            message,
+        });
+    }
+
+    pub fn append(&mut self, other: &mut CoverageReport) {
+        self.entries.append(&mut other.entries);
+    }
+
+    pub fn count(&self, coverage: Coverage) -> usize {
+        self.entries.iter().filter(|x| x.coverage == coverage).count()
+    }
+
+    pub fn write<T: Write>(&self, writer: &mut T)
+            -> Result<(), std::io::Error> {
+        writeln!(writer, "timestamp,geohash,coverage,message")?;
+        for entry in self.entries.iter() {
+            let message = match &entry.message {
+                Some(message) => message.replace(",", ";"),
+                None => String::new(),
+            };
+
+            writeln!(writer, "{},{},{},{}", entry.timestamp,
+                entry.geohash, entry.coverage, message)?;
+        }
+
+        Ok(())
+    }
+}
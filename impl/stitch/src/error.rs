@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum StitchError {
+    /// no imagery was found for the requested area and time
+    NoImagery(String),
+    /// the stip cluster could not map a geocode to a node
+    NodeLocate(String),
+    /// failed to establish a grpc connection
+    Transport(tonic::transport::Error),
+    /// a grpc call returned an error status
+    Rpc(tonic::Status),
+    /// network failure on a transfer protocol connection
+    Io(std::io::Error),
+    /// error message reported by a stip or stitchd server
+    Remote(String),
+    /// failed to decode a transfer protocol response
+    Deserialize(String),
+    /// invalid configuration
+    Config(String),
+    /// failed to compute a geocode
+    Geocode(String),
+    /// images lack the requested bands
+    MissingBands(String),
+    /// failed to merge, split or write a dataset
+    Gdal(String),
+    /// unexpected failure within the reconstruction pipeline
+    Internal(String),
+}
+
+impl Display for StitchError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            StitchError::NoImagery(msg) =>
+                write!(f, "no imagery available: {}", msg),
+            StitchError::NodeLocate(msg) =>
+                write!(f, "node lookup failed: {}", msg),
+            StitchError::Transport(e) =>
+                write!(f, "grpc transport error: {}", e),
+            StitchError::Rpc(status) =>
+                write!(f, "grpc request failed: {}", status),
+            StitchError::Io(e) => write!(f, "io error: {}", e),
+            StitchError::Remote(msg) =>
+                write!(f, "remote error: {}", msg),
+            StitchError::Deserialize(msg) =>
+                write!(f, "deserialization error: {}", msg),
+            StitchError::Config(msg) =>
+                write!(f, "configuration error: {}", msg),
+            StitchError::Geocode(msg) =>
+                write!(f, "geocode error: {}", msg),
+            StitchError::MissingBands(msg) =>
+                write!(f, "missing bands: {}", msg),
+            StitchError::Gdal(msg) => write!(f, "gdal error: {}", msg),
+            StitchError::Internal(msg) =>
+                write!(f, "internal error: {}", msg),
+        }
+    }
+}
+
+impl StitchError {
+    /// network failures which may succeed on another node
+    pub fn is_transient(&self) -> bool {
+        matches!(self, StitchError::Transport(_) | StitchError::Io(_))
+    }
+}
+
+impl Error for StitchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StitchError::Transport(e) => Some(e),
+            StitchError::Rpc(status) => Some(status),
+            StitchError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for StitchError {
+    fn from(err: tonic::transport::Error) -> StitchError {
+        StitchError::Transport(err)
+    }
+}
+
+impl From<tonic::Status> for StitchError {
+    fn from(status: tonic::Status) -> StitchError {
+        StitchError::Rpc(status)
+    }
+}
+
+impl From<std::io::Error> for StitchError {
+    fn from(err: std::io::Error) -> StitchError {
+        StitchError::Io(err)
+    }
+}
+
+impl From<yogi::YogiError> for StitchError {
+    fn from(err: yogi::YogiError) -> StitchError {
+        match err {
+            yogi::YogiError::Transport(e) => StitchError::Transport(e),
+            yogi::YogiError::Rpc(status) => StitchError::Rpc(status),
+            yogi::YogiError::Io(e) => StitchError::Io(e),
+            yogi::YogiError::Remote(msg) => StitchError::Remote(msg),
+            yogi::YogiError::Deserialize(msg) =>
+                StitchError::Deserialize(msg),
+            yogi::YogiError::Serialize(msg) => StitchError::Internal(
+                format!("failed to encode request: {}", msg)),
+            yogi::YogiError::MissingBands(msg) =>
+                StitchError::MissingBands(msg),
+            yogi::YogiError::Config(msg) => StitchError::Config(msg),
+        }
+    }
+}
+
+impl From<std::string::FromUtf8Error> for StitchError {
+    fn from(err: std::string::FromUtf8Error) -> StitchError {
+        StitchError::Deserialize(err.to_string())
+    }
+}
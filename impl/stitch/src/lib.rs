@@ -0,0 +1,604 @@
+use gdal::Dataset;
+use futures::StreamExt;
+use protobuf::{Filter, Image, Node};
+use geocode::Geocode;
+use tokio::runtime::Runtime;
+use yogi::{BandSet, ClientPool, ConnectionConfig, EndpointRegistry, YogiError};
+use yogi::protocol::Version;
+
+mod cache;
+pub use cache::TileCache;
+mod coverage;
+pub use coverage::{Coverage, CoverageEntry, CoverageReport};
+mod error;
+pub use error::StitchError;
+mod locator;
+pub use locator::NodeLocator;
+mod output;
+pub use output::{Format, write};
+mod polygon;
+pub use polygon::Polygon;
+mod progress;
+pub use progress::{Progress, ProgressEvent, SilentProgress};
+pub mod raster;
+pub use raster::{Resampling, Warp};
+pub use yogi::{ConfigPolicy, DefaultPolicy, SelectionConfig, SelectionPolicy, Weights};
+mod tile;
+pub use tile::Tile;
+
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct Bounds {
+    pub min_latitude: f64,
+    pub max_latitude: f64,
+    pub min_longitude: f64,
+    pub max_longitude: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReconstructionRequest {
+    album: String,
+    bands: BandSet,
+    bounds: Bounds,
+    cache: Option<Arc<TileCache>>,
+    connection: ConnectionConfig,
+    download_count: usize,
+    endpoints: EndpointRegistry,
+    geocode: Geocode,
+    locator: Arc<NodeLocator>,
+    partial: bool,
+    policy: Arc<dyn SelectionPolicy>,
+    polygon: Option<Polygon>,
+    precision: usize,
+    progress: Arc<dyn Progress>,
+    request_count: usize,
+    /// transfer protocol version of stitchd requests
+    stitchd_version: Version,
+    timestamp: i64,
+    warp: Option<Warp>,
+}
+
+impl ReconstructionRequest {
+    /// reconstruct true color geohash tiles of precision 5 from the stip
+    /// node at 127.0.0.1:15606, without a tile cache
+    pub fn new(album: String, bounds: Bounds, timestamp: i64)
+            -> ReconstructionRequest {
+        ReconstructionRequest {
+            album,
+            bands: BandSet::default(),
+            bounds,
+            cache: None,
+            connection: ConnectionConfig::default(),
+            download_count: 8,
+            endpoints: EndpointRegistry::default(),
+            geocode: Geocode::Geohash,
+            locator: Arc::new(NodeLocator::new(
+                "127.0.0.1:15606".to_string(), None)),
+            partial: false,
+            policy: Arc::new(DefaultPolicy::default()),
+            polygon: None,
+            precision: 5,
+            progress: Arc::new(SilentProgress::default()),
+            request_count: 4,
+            stitchd_version: Version::V0,
+            timestamp,
+            warp: None,
+        }
+    }
+
+    pub fn with_bands(mut self, bands: BandSet) -> ReconstructionRequest {
+        self.bands = bands;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: TileCache) -> ReconstructionRequest {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    pub fn with_connection(mut self, connection: ConnectionConfig)
+            -> ReconstructionRequest {
+        self.connection = connection;
+        self
+    }
+
+    /// number of tiles downloaded concurrently
+    pub fn with_download_count(mut self, download_count: usize)
+            -> ReconstructionRequest {
+        self.download_count = download_count;
+        self
+    }
+
+    pub fn with_endpoints(mut self, endpoints: EndpointRegistry)
+            -> ReconstructionRequest {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// geocode system and precision of the album partitioning
+    pub fn with_geocode(mut self, geocode: Geocode, precision: usize)
+            -> ReconstructionRequest {
+        self.geocode = geocode;
+        self.precision = precision;
+        self
+    }
+
+    pub fn with_locator(mut self, locator: NodeLocator)
+            -> ReconstructionRequest {
+        self.locator = Arc::new(locator);
+        self
+    }
+
+    /// fill unavailable tiles with no-data rather than failing
+    pub fn with_partial(mut self, partial: bool) -> ReconstructionRequest {
+        self.partial = partial;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: Arc<dyn SelectionPolicy>)
+            -> ReconstructionRequest {
+        self.policy = policy;
+        self
+    }
+
+    /// restrict the reconstruction to an area of interest within bounds
+    pub fn with_polygon(mut self, polygon: Polygon)
+            -> ReconstructionRequest {
+        self.polygon = Some(polygon);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Arc<dyn Progress>)
+            -> ReconstructionRequest {
+        self.progress = progress;
+        self
+    }
+
+    /// number of concurrent grpc requests
+    pub fn with_request_count(mut self, request_count: usize)
+            -> ReconstructionRequest {
+        self.request_count = request_count;
+        self
+    }
+
+    pub fn with_stitchd_version(mut self, stitchd_version: Version)
+            -> ReconstructionRequest {
+        self.stitchd_version = stitchd_version;
+        self
+    }
+
+    pub fn with_warp(mut self, warp: Warp) -> ReconstructionRequest {
+        self.warp = Some(warp);
+        self
+    }
+}
+
+type Window = (f64, f64, f64, f64);
+
+pub fn reconstruct(request: &ReconstructionRequest)
+        -> Result<Dataset, StitchError> {
+    let (dataset, _) = reconstruct_with_coverage(request)?;
+    Ok(dataset)
+}
+
+pub fn reconstruct_with_coverage(request: &ReconstructionRequest)
+        -> Result<(Dataset, CoverageReport), StitchError> {
+    let pool = ClientPool::new();
+    runtime()?.block_on(async {
+        let targets = locate_targets(request, &pool).await?;
+        let tiles = select_tiles(request,
+            &pool, &targets, request.timestamp).await?;
+        assemble(request, &pool, tiles, request.timestamp).await
+    })
+}
+
+pub fn reconstruct_series(request: &ReconstructionRequest,
+        timestamps: &[i64])
+        -> Result<Vec<(i64, Dataset, CoverageReport)>, StitchError> {
+    let pool = ClientPool::new();
+    runtime()?.block_on(async {
+        // geohash windows and nodes are shared across all timestamps
+        let targets = locate_targets(request, &pool).await?;
+
+        let mut results = Vec::new();
+        for timestamp in timestamps.iter() {
+            request.progress.report(ProgressEvent::Timestamp(*timestamp));
+            let tiles = select_tiles(request,
+                &pool, &targets, *timestamp).await?;
+            match assemble(request, &pool, tiles, *timestamp).await {
+                Ok((dataset, coverage_report)) =>
+                    results.push((*timestamp, dataset, coverage_report)),
+                Err(StitchError::NoImagery(msg)) => request.progress
+                    .report(ProgressEvent::Skipped {
+                        timestamp: *timestamp,
+                        reason: &msg,
+                    }),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if results.is_empty() {
+            return Err(StitchError::NoImagery(
+                "no tiles found for any timestamp".to_string()));
+        }
+
+        Ok(results)
+    })
+}
+
+/// query the capabilities of each stitchd endpoint so requests are
+/// validated before they are sent, returning those which failed
+pub fn describe_endpoints(endpoints: &mut EndpointRegistry,
+        version: Version, connection: &ConnectionConfig)
+        -> Result<Vec<(String, YogiError)>, StitchError> {
+    Ok(runtime()?.block_on(endpoints.describe(version, connection)))
+}
+
+fn runtime() -> Result<Runtime, StitchError> {
+    tokio::runtime::Builder::new()
+        .basic_scheduler().enable_all().build()
+        .map_err(|e| StitchError::Internal(
+            format!("failed to start runtime: {}", e)))
+}
+
+async fn locate_targets(request: &ReconstructionRequest, pool: &ClientPool)
+        -> Result<Vec<(String, Window, Node)>, StitchError> {
+    let bounds = &request.bounds;
+
+    // identify geohash windows in bounding box
+    let geocode = &request.geocode;
+    let (longitude_interval, latitude_interval) =
+        geocode.get_intervals(request.precision);
+    let windows = st_image::coordinate::get_windows(
+        bounds.min_longitude, bounds.max_longitude,
+        bounds.min_latitude, bounds.max_latitude,
+        longitude_interval, latitude_interval);
+
+    // skip windows outside the area of interest
+    let windows = match &request.polygon {
+        Some(polygon) => polygon.filter(&windows)?,
+        None => windows,
+    };
+
+    // compute window geohashes
+    let mut geohashes = Vec::new();
+    for window in windows.iter() {
+        let (min_long, max_long, min_lat, max_lat) = window;
+        let geohash = geocode.encode((min_long + max_long) / 2.0,
+                (min_lat + max_lat) / 2.0, request.precision)
+            .map_err(|e| StitchError::Geocode(e.to_string()))?;
+
+        geohashes.push((geohash, *window));
+    }
+
+    // find node responsible for each geohash
+    let locator = &request.locator;
+    let mut stream = futures::stream::iter(geohashes)
+        .map(|(geohash, window)| async move {
+            let node = locator.locate(pool, &geohash).await?;
+            Ok::<_, StitchError>((geohash, window, node))
+        })
+        .buffer_unordered(request.request_count.max(1));
+
+    let mut targets = Vec::new();
+    while let Some(result) = stream.next().await {
+        targets.push(result?);
+    }
+
+    // persist node locations for subsequent runs
+    if let Err(e) = locator.save() {
+        request.progress.report(ProgressEvent::Warning(
+            format!("failed to save node cache: {}", e)));
+    }
+
+    targets.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(targets)
+}
+
+async fn select_tiles(request: &ReconstructionRequest, pool: &ClientPool,
+        targets: &[(String, Window, Node)], timestamp: i64)
+        -> Result<Vec<(String, Window, Option<Tile>)>, StitchError> {
+    let mut stream = futures::stream::iter(targets.iter())
+        .map(|(geohash, window, node)|
+            select_tile(request, pool, geohash, window, node, timestamp))
+        .buffer_unordered(request.request_count.max(1));
+
+    let mut tiles = Vec::new();
+    while let Some(result) = stream.next().await {
+        tiles.push(result?);
+    }
+
+    tiles.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(tiles)
+}
+
+async fn select_tile(request: &ReconstructionRequest, pool: &ClientPool,
+        geohash: &str, window: &Window, node: &Node, timestamp: i64)
+        -> Result<(String, Window, Option<Tile>), StitchError> {
+    let policy = &request.policy;
+    let unavailable = |reason: &str| {
+        request.progress.report(ProgressEvent::Unavailable {
+            geohash,
+            reason,
+        });
+        Ok((geohash.to_string(), *window, None))
+    };
+
+    // retrieve sentinel-2 images
+    let sentinel2_filter = policy.sentinel2_filter(geohash, timestamp);
+    let sentinel2_images = get_images(pool,
+        &request.album, sentinel2_filter, &node.rpc_addr).await?;
+
+    let count = sentinel2_images.len();
+    let sentinel2_images: Vec<Image> = sentinel2_images.into_iter()
+        .filter(|x| request.bands.layout(x).is_some()).collect();
+
+    request.progress.report(ProgressEvent::ImagesFound {
+        geohash,
+        platform: "sentinel-2",
+        count: sentinel2_images.len(),
+    });
+    if sentinel2_images.is_empty() && count != 0 {
+        let msg = format!("none of the {} sentinel-2 image(s) for {} \
+            contain bands {}", count, geohash, request.bands);
+        if !request.partial {
+            return Err(StitchError::MissingBands(msg));
+        }
+
+        // partial reconstructions fill the geohash with no-data
+        return unavailable(&msg);
+    }
+
+    // if sentinel-2 image on timestamp -> use stip
+    if let Some(image) = policy.select_stip(timestamp, &sentinel2_images) {
+        request.progress.report(ProgressEvent::StipImage {
+            geohash,
+            timestamp: image.timestamp,
+        });
+        let layout = request.bands.resolve(&image)?;
+        let subgeohashes = subgeohashes(request, geohash, window)?;
+        let tile = Tile::Stip(node.clone(), image, layout, subgeohashes);
+        return Ok((geohash.to_string(), *window, Some(tile)));
+    }
+
+    // imputation only produces true color images
+    if request.bands != BandSet::TrueColor {
+        return unavailable("bands cannot be imputed");
+    }
+
+    // imputation requires a stitchd server fit on the geohash
+    if !request.endpoints.is_routable(geohash) {
+        return unavailable("no stitchd server handles geohash");
+    }
+
+    // retrieve modis images
+    let modis_filter = policy.modis_filter(geohash, timestamp);
+    let modis_images = get_images(pool,
+        &request.album, modis_filter, &node.rpc_addr).await?;
+
+    let modis_images: Vec<Image> = modis_images.into_iter()
+        .filter(|x| yogi::modis_file(x).is_some()).collect();
+
+    // if enough sentinel-2 and modis images -> use SATnet
+    request.progress.report(ProgressEvent::ImagesFound {
+        geohash,
+        platform: "modis",
+        count: modis_images.len(),
+    });
+    if let Some((sentinel2_images, modis_image)) = policy
+            .select_stitch(timestamp, &sentinel2_images, &modis_images) {
+        // validate the request against stitchd capabilities
+        let endpoints = request.endpoints.resolve(&node.xfer_addr,
+            &sentinel2_images, &modis_image);
+        if endpoints.is_empty() {
+            return unavailable(
+                "no stitchd server accepts imputation request");
+        }
+
+        let tile = Tile::Stitch(node.clone(),
+            endpoints, sentinel2_images, modis_image);
+        return Ok((geohash.to_string(), *window, Some(tile)));
+    }
+
+    unavailable("too few images to impute")
+}
+
+async fn download(request: &ReconstructionRequest, pool: &ClientPool,
+        geohash: &str, tile: &Tile) -> Result<Vec<Dataset>, StitchError> {
+    let cache = match &request.cache {
+        Some(cache) => cache,
+        None => return download_with_relookup(
+            request, pool, geohash, tile).await,
+    };
+
+    // check cache
+    let key = tile.cache_key()?;
+    match cache.get(&key).await {
+        Ok(Some(datasets)) => return Ok(datasets),
+        Ok(None) => {},
+        Err(e) => request.progress.report(ProgressEvent::Warning(
+            format!("failed to read cached tile: {}", e))),
+    }
+
+    let datasets =
+        download_with_relookup(request, pool, geohash, tile).await?;
+    if let Err(e) = cache.put(&key, &datasets).await {
+        request.progress.report(ProgressEvent::Warning(
+            format!("failed to cache tile: {}", e)));
+    }
+
+    Ok(datasets)
+}
+
+async fn download_with_relookup(request: &ReconstructionRequest,
+        pool: &ClientPool, geohash: &str, tile: &Tile)
+        -> Result<Vec<Dataset>, StitchError> {
+    let config = &request.connection;
+    let err = match tile.download(request.stitchd_version, config).await {
+        Ok(datasets) => return Ok(datasets),
+        Err(e) => e,
+    };
+
+    // retry stip reads once if the geohash has moved to another node
+    let (node, image, layout, subgeocodes) = match tile {
+        Tile::Stip(node, image, layout, subgeocodes)
+            if err.is_transient() => (node, image, layout, subgeocodes),
+        _ => return Err(err),
+    };
+
+    let moved_node = match request.locator
+            .relookup(pool, geohash, node).await? {
+        Some(moved_node) => moved_node,
+        None => return Err(err),
+    };
+
+    request.progress.report(ProgressEvent::Relookup {
+        geohash,
+        from: &node.xfer_addr,
+        to: &moved_node.xfer_addr,
+        error: &err,
+    });
+    config.stats.record_relookup();
+
+    let tile = Tile::Stip(moved_node, image.clone(),
+        layout.clone(), subgeocodes.clone());
+    tile.download(request.stitchd_version, config).await
+}
+
+/// sub-geohashes of a window which intersect the bounds, or none if
+/// the bounds cover the entire window
+fn subgeohashes(request: &ReconstructionRequest, geohash: &str,
+        window: &Window) -> Result<Vec<String>, StitchError> {
+    let bounds = &request.bounds;
+    let (min_long, max_long, min_lat, max_lat) = *window;
+    if bounds.min_longitude <= min_long && bounds.max_longitude >= max_long
+            && bounds.min_latitude <= min_lat
+            && bounds.max_latitude >= max_lat {
+        return Ok(Vec::new());
+    }
+
+    // identify sub-geohash windows in the intersection
+    let geocode = &request.geocode;
+    let precision = request.precision + 1;
+    let (longitude_interval, latitude_interval) =
+        geocode.get_intervals(precision);
+    let windows = st_image::coordinate::get_windows(
+        min_long.max(bounds.min_longitude),
+        max_long.min(bounds.max_longitude),
+        min_lat.max(bounds.min_latitude),
+        max_lat.min(bounds.max_latitude),
+        longitude_interval, latitude_interval);
+
+    let mut subgeohashes = Vec::new();
+    for (min_long, max_long, min_lat, max_lat) in windows {
+        let subgeohash = geocode.encode((min_long + max_long) / 2.0,
+                (min_lat + max_lat) / 2.0, precision)
+            .map_err(|e| StitchError::Geocode(e.to_string()))?;
+
+        // windows on the edges may fall outside of the geohash
+        if subgeohash.starts_with(geohash)
+                && !subgeohashes.contains(&subgeohash) {
+            subgeohashes.push(subgeohash);
+        }
+    }
+
+    Ok(subgeohashes)
+}
+
+async fn assemble(request: &ReconstructionRequest, pool: &ClientPool,
+        tiles: Vec<(String, Window, Option<Tile>)>, timestamp: i64)
+        -> Result<(Dataset, CoverageReport), StitchError> {
+    let bounds = &request.bounds;
+
+    // split tiles into downloads and unavailable windows
+    let mut coverage_report = CoverageReport::default();
+    let mut empty_windows = Vec::new();
+    let mut downloads = Vec::new();
+    for (geohash, window, tile) in tiles.iter() {
+        match tile {
+            Some(tile) => downloads.push(async move {
+                (geohash, window, tile,
+                    download(request, pool, geohash, tile).await)
+            }),
+            None => {
+                coverage_report.push(geohash, timestamp, Coverage::Empty,
+                    Some("image unavailable".to_string()));
+                empty_windows.push(*window);
+            },
+        }
+    }
+
+    // download images concurrently, collecting datasets as they complete
+    let mut datasets = Vec::new();
+    let mut stream = futures::stream::iter(downloads)
+        .buffer_unordered(request.download_count.max(1));
+    while let Some((geohash, window, tile, result)) = stream.next().await {
+        let coverage = tile.coverage();
+        match result {
+            Ok(mut tile_datasets) => {
+                coverage_report.push(geohash, timestamp, coverage, None);
+                datasets.append(&mut tile_datasets);
+            },
+            Err(e) if request.partial => {
+                request.progress.report(ProgressEvent::DownloadFailed {
+                    geohash,
+                    error: &e,
+                });
+                coverage_report.push(geohash, timestamp,
+                    Coverage::Empty, Some(e.to_string()));
+                empty_windows.push(*window);
+            },
+            Err(e) => return Err(e),
+        }
+    }
+
+    coverage_report.entries.sort_by(|a, b| a.geohash.cmp(&b.geohash));
+
+    if datasets.is_empty() {
+        return Err(StitchError::NoImagery(format!(
+            "no tiles found for timestamp {}", timestamp)));
+    }
+
+    // fill unavailable tiles with no-data
+    if request.partial {
+        for (min_long, max_long, min_lat, max_lat) in empty_windows {
+            let dataset = raster::empty_dataset(&datasets[0],
+                min_long, max_long, min_lat, max_lat)?;
+            datasets.push(dataset);
+        }
+    }
+
+    // merge datasets
+    let dataset = st_image::transform::merge(&datasets).map_err(|e|
+        StitchError::Gdal(format!("failed to merge datasets: {}", e)))?;
+
+    // split image on provided bounds
+    let dataset = st_image::transform::split(&dataset,
+            bounds.min_longitude, bounds.max_longitude,
+            bounds.min_latitude, bounds.max_latitude, 4326)
+        .map_err(|e| StitchError::Gdal(
+            format!("failed to trim dataset: {}", e)))?;
+
+    // reproject onto requested grid
+    let dataset = match &request.warp {
+        Some(warp) => raster::warp(&dataset, warp)?,
+        None => dataset,
+    };
+
+    // mask pixels outside the area of interest
+    if let Some(polygon) = &request.polygon {
+        polygon.mask(&dataset)?;
+    }
+
+    Ok((dataset, coverage_report))
+}
+
+async fn get_images(pool: &ClientPool, album: &str, filter: Filter,
+        rpc_address: &str) -> Result<Vec<Image>, StitchError> {
+    let mut images = pool.list_images(rpc_address, album, filter).await?;
+
+    // sort in descending order by timstamp
+    images.sort_by(|a, b| b.timestamp.partial_cmp(&a.timestamp).unwrap());
+    Ok(images)
+}
@@ -0,0 +1,233 @@
+use protobuf::Node;
+use yogi::ClientPool;
+
+use crate::StitchError;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// maps geohashes to stip nodes, caching results by geohash prefix
+#[derive(Debug)]
+pub struct NodeLocator {
+    rpc_addr: String,
+    prefix_length: Option<usize>,
+    cache: RwLock<HashMap<String, (Node, u64)>>,
+    cache_file: Option<(PathBuf, u64)>,
+}
+
+impl NodeLocator {
+    pub fn new(rpc_addr: String, prefix_length: Option<usize>)
+            -> NodeLocator {
+        NodeLocator {
+            rpc_addr,
+            prefix_length,
+            cache: RwLock::new(HashMap::new()),
+            cache_file: None,
+        }
+    }
+
+    /// load unexpired entries from, and later save entries to, a file
+    pub fn with_cache_file(mut self, path: &Path, ttl: u64)
+            -> Result<NodeLocator, StitchError> {
+        if path.exists() {
+            let file = File::open(path)?;
+            let now = now();
+
+            let mut cache = self.cache.write().unwrap();
+            for line in BufReader::new(file).lines() {
+                // parse 'prefix id rpc_addr xfer_addr timestamp' lines
+                let line = line?;
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() != 5 {
+                    continue;
+                }
+
+                let (id, timestamp) = match (fields[1].parse::<u32>(),
+                        fields[4].parse::<u64>()) {
+                    (Ok(id), Ok(timestamp)) => (id, timestamp),
+                    _ => continue,
+                };
+
+                if timestamp + ttl < now {
+                    continue;
+                }
+
+                let node = Node {
+                    id,
+                    rpc_addr: fields[2].to_string(),
+                    xfer_addr: fields[3].to_string(),
+                };
+
+                cache.insert(fields[0].to_string(), (node, timestamp));
+            }
+        }
+
+        self.cache_file = Some((path.to_path_buf(), ttl));
+        Ok(self)
+    }
+
+    pub async fn locate(&self, pool: &ClientPool, geohash: &str)
+            -> Result<Node, StitchError> {
+        let key = self.key(geohash);
+
+        // check cache
+        if let Some((node, _)) = self.cache.read().unwrap().get(key) {
+            return Ok(node.clone());
+        }
+
+        // query node responsible for geohash
+        let node = match pool.locate(&self.rpc_addr, geohash).await? {
+            Some(node) => node,
+            None => return Err(StitchError::NodeLocate(
+                format!("failed to locate geocode '{}'", geohash))),
+        };
+
+        let mut cache = self.cache.write().unwrap();
+        cache.insert(key.to_string(), (node.clone(), now()));
+        Ok(node)
+    }
+
+    /// look up the node for geohash again, bypassing the cache, returning
+    /// it only if it differs from the failed node
+    ///
+    /// this is not a failover to a replica, stip stores each geohash on a
+    /// single node, so a relookup only helps after the geohash has moved
+    pub async fn relookup(&self, pool: &ClientPool, geohash: &str,
+            failed: &Node) -> Result<Option<Node>, StitchError> {
+        self.cache.write().unwrap().remove(self.key(geohash));
+
+        let node = self.locate(pool, geohash).await?;
+        if node.xfer_addr == failed.xfer_addr {
+            return Ok(None);
+        }
+
+        Ok(Some(node))
+    }
+
+    /// write cached entries to the cache file, if one is configured
+    pub fn save(&self) -> Result<(), StitchError> {
+        let path = match &self.cache_file {
+            Some((path, _)) => path,
+            None => return Ok(()),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        for (key, (node, timestamp)) in self.cache.read().unwrap().iter() {
+            writeln!(writer, "{} {} {} {} {}", key, node.id,
+                node.rpc_addr, node.xfer_addr, timestamp)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn key<'a>(&self, geohash: &'a str) -> &'a str {
+        match self.prefix_length {
+            Some(length) if length < geohash.len() => &geohash[..length],
+            _ => geohash,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no stip node listens here, so lookups missing the cache fail
+    const RPC_ADDR: &str = "127.0.0.1:1";
+
+    fn cache_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "stitch-locator-{}-{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn node(id: u32) -> Node {
+        Node {
+            id,
+            rpc_addr: format!("10.0.0.{}:15606", id),
+            xfer_addr: format!("10.0.0.{}:15607", id),
+        }
+    }
+
+    #[test]
+    fn cache_file_load_and_save() {
+        let now = now();
+        let path = cache_file("load", &format!(
+            "9xj 1 10.0.0.1:15606 10.0.0.1:15607 {}\n\
+            9xk 2 10.0.0.2:15606 10.0.0.2:15607 {}\n\
+            9xm 3 10.0.0.3:15606\n\
+            9xn x 10.0.0.4:15606 10.0.0.4:15607 {}\n",
+            now, now - 7200, now));
+
+        // expired and malformed entries are dropped
+        let locator = NodeLocator::new(RPC_ADDR.to_string(), Some(3))
+            .with_cache_file(&path, 3600).unwrap();
+        {
+            let cache = locator.cache.read().unwrap();
+            assert_eq!(cache.len(), 1);
+            assert_eq!(cache.get("9xj"), Some(&(node(1), now)));
+        }
+
+        locator.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!(
+            "9xj 1 10.0.0.1:15606 10.0.0.1:15607 {}\n", now));
+
+        // a missing file starts an empty cache
+        std::fs::remove_file(&path).unwrap();
+        let locator = NodeLocator::new(RPC_ADDR.to_string(), Some(3))
+            .with_cache_file(&path, 3600).unwrap();
+        assert!(locator.cache.read().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn locate_by_prefix() {
+        let path = cache_file("prefix", &format!(
+            "9xj 1 10.0.0.1:15606 10.0.0.1:15607 {}\n", now()));
+        let locator = NodeLocator::new(RPC_ADDR.to_string(), Some(3))
+            .with_cache_file(&path, 3600).unwrap();
+        let pool = ClientPool::new();
+
+        assert_eq!(locator.locate(&pool, "9xj3e").await.unwrap(), node(1));
+        assert_eq!(locator.locate(&pool, "9xj").await.unwrap(), node(1));
+        assert!(locator.locate(&pool, "9xk3e").await.is_err());
+
+        // without a prefix length entries match whole geohashes
+        let locator = NodeLocator::new(RPC_ADDR.to_string(), None)
+            .with_cache_file(&path, 3600).unwrap();
+        assert_eq!(locator.locate(&pool, "9xj").await.unwrap(), node(1));
+        assert!(locator.locate(&pool, "9xj3e").await.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn relookup_invalidates_cache() {
+        let path = cache_file("relookup", &format!(
+            "9xj 1 10.0.0.1:15606 10.0.0.1:15607 {}\n\
+            9xk 2 10.0.0.2:15606 10.0.0.2:15607 {}\n", now(), now()));
+        let locator = NodeLocator::new(RPC_ADDR.to_string(), Some(3))
+            .with_cache_file(&path, 3600).unwrap();
+        let pool = ClientPool::new();
+
+        // the lookup fails, but only after the stale entry is removed
+        assert!(locator.relookup(&pool, "9xj3e", &node(1)).await.is_err());
+        assert!(locator.locate(&pool, "9xj3e").await.is_err());
+        assert_eq!(locator.locate(&pool, "9xk3e").await.unwrap(), node(2));
+
+        locator.save().unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("9xj"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
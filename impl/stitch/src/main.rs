@@ -1,18 +1,17 @@
-use gdal::{Dataset, Driver};
-use failure::ResultExt;
-use protobuf::{Filter, Image, ImageListRequest, ImageManagementClient, Node, NodeLocateRequest, NodeManagementClient};
-use structopt::StructOpt;
+use gdal::Dataset;
 use geocode::Geocode;
-use tonic::Request;
-
-mod tile;
-use tile::Tile;
+use stitch::{Bounds, ConfigPolicy, Coverage, CoverageReport, DefaultPolicy, Format, NodeLocator, Polygon, Progress, ProgressEvent, ReconstructionRequest, Resampling, SelectionConfig, SelectionPolicy, StitchError, TileCache, Warp};
+use structopt::StructOpt;
+use structopt::clap;
+use yogi::{BandSet, ConnectionOpt, EndpointRegistry};
+use yogi::protocol::Version;
 
-use std::error::Error;
-use std::ffi::{CStr, CString};
+use std::fmt::Display;
+use std::fs::File;
 use std::net::IpAddr;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, StructOpt)]
 #[structopt(name="stitch")]
@@ -20,322 +19,482 @@ struct Opt {
     #[structopt(short, long, help="stip album", default_value="test")]
     album: String,
 
+    #[structopt(short="o", long="creation-option", number_of_values=1,
+        help="gdal creation option KEY=VALUE, may be repeated")]
+    creation_options: Vec<String>,
+
+    #[structopt(short, long, help="sentinel-2 bands to download ('rgb', \
+        'nir', 'all', or a list like 'B4,B8')", default_value="rgb")]
+    bands: BandSet,
+
+    #[structopt(long, help="tile cache directory \
+        [default: $HOME/.cache/stitch]")]
+    cache_directory: Option<PathBuf>,
+
+    #[structopt(long,
+        help="tile cache size limit in megabytes", default_value="1024")]
+    cache_size: u64,
+
+    #[structopt(flatten)]
+    connection: ConnectionOpt,
+
+    #[structopt(short, long,
+        help="concurrent download count", default_value="8")]
+    download_count: usize,
+
+    #[structopt(short, long,
+        help="time series end timestamp")]
+    end: Option<i64>,
+
+    #[structopt(short, long, help="output format ('gtiff', 'cog', \
+        'netcdf', 'png', 'jpeg', or 'serialize')", default_value="gtiff")]
+    format: Format,
+
+    #[structopt(short, long, help="geocode system of the album \
+        ('geohash' or 'quadtile')", default_value="geohash",
+        parse(try_from_str=parse_geocode))]
+    geocode: Geocode,
+
+    #[structopt(short="c", long,
+        help="maximum sentinel-2 cloud coverage percentage")]
+    max_cloud_coverage: Option<f64>,
+
     #[structopt(short, long,
         help="stip node ip address", default_value="127.0.0.1")]
     ip_address: IpAddr,
 
-    #[structopt(name="MIN_LATITUDE", help="minimum bounding latitude")]
-    min_latitude: f64,
+    #[structopt(long, help="file caching geohash to node mappings")]
+    node_cache: Option<PathBuf>,
+
+    #[structopt(long, help="node cache entry lifetime in seconds",
+        default_value="86400")]
+    node_cache_ttl: u64,
 
-    #[structopt(name="MAX_LATITUDE", help="maximum bounding latitude")]
-    max_latitude: f64,
+    #[structopt(long, help="geohash prefix length used to cache \
+        node mappings, defaults to the full geohash")]
+    node_prefix_length: Option<usize>,
 
-    #[structopt(name="MIN_LONGITUDE", help="minimum bounding longitude")]
-    min_longitude: f64,
+    #[structopt(long, help="disable the tile cache")]
+    no_cache: bool,
 
-    #[structopt(name="MAX_LONGITUDE", help="maximum bounding longtude")]
-    max_longitude: f64,
+    #[structopt(long, help="fill unavailable tiles with no-data \
+        and write a coverage report")]
+    partial: bool,
+
+    #[structopt(long, help="area of interest as a GeoJSON or WKT \
+        polygon, inline or in a file, replacing the bounding box")]
+    polygon: Option<String>,
 
     #[structopt(short, long,
         help="stip node rpc port", default_value="15606")]
     port: u16,
 
-    #[structopt(short, long, help="thread count", default_value="4")]
-    thread_count: u8,
+    #[structopt(long, help="geocode precision of the album \
+        partitioning", default_value="5")]
+    precision: usize,
+
+    #[structopt(long,
+        help="download tiles even if cached, updating the cache")]
+    refresh: bool,
+
+    #[structopt(long, help="warp resampling method ('nearest', \
+        'bilinear', 'cubic', 'cubicspline', 'lanczos', 'average', or \
+        'mode')", default_value="nearest")]
+    resampling: Resampling,
+
+    #[structopt(long, help="output pixel size in target srs units, \
+        as 'SIZE' or 'X,Y'", conflicts_with="size",
+        parse(try_from_str=parse_resolution))]
+    resolution: Option<(f64, f64)>,
+
+    #[structopt(short, long, help="tile selection configuration file")]
+    selection_config: Option<PathBuf>,
 
-    #[structopt(name="TIMESTAMP", help="image timestamp")]
-    timestamp: i64,
+    #[structopt(long, help="output dimensions in pixels, as \
+        'WIDTH,HEIGHT'", parse(try_from_str=parse_size))]
+    size: Option<(u32, u32)>,
 
-    #[structopt(name="OUTPUT_FILE", help="image output file")]
-    output_file: PathBuf,
+    #[structopt(long, help="write time series as a single \
+        multi-band file rather than one file per timestamp",
+        requires="end")]
+    stack: bool,
+
+    #[structopt(long, help="time series start timestamp, replaces \
+        TIMESTAMP", requires="end")]
+    start: Option<i64>,
+
+    #[structopt(long,
+        help="time series step in days", default_value="1")]
+    step: u32,
+
+    #[structopt(long, help="stitchd hosts file of 'host port \
+        [-g GEOHASH]...' lines (ex. 'etc/hosts.txt'), defaults to port \
+        12289 on each stip host")]
+    stitchd_hosts: Option<PathBuf>,
+
+    #[structopt(long, help="stitchd transfer protocol version, v1 \
+        enables capability checks", default_value="0")]
+    stitchd_protocol_version: Version,
+
+    #[structopt(long, help="output spatial reference system \
+        (ex. 'EPSG:32613'), defaults to the stored projection")]
+    target_srs: Option<String>,
+
+    #[structopt(short, long,
+        help="concurrent grpc request count", default_value="4")]
+    thread_count: usize,
+
+    #[structopt(name="ARGS", required=true, help="[MIN_LATITUDE \
+        MAX_LATITUDE MIN_LONGITUDE MAX_LONGITUDE] [TIMESTAMP] \
+        OUTPUT_FILE, the bounding box is omitted with --polygon, \
+        TIMESTAMP is optional with --start, OUTPUT_FILE may be '-' to \
+        serialize to stdout")]
+    args: Vec<String>,
 }
 
 fn main() {
     // parse command line options
     let opt = Opt::from_args();
 
-    // identify geohash windows in bounding box
-    let geocode = Geocode::Geohash;
-    let (longitude_interval, latitude_interval) =
-        geocode.get_intervals(5);
-    let windows = st_image::coordinate::get_windows(
-        opt.min_longitude, opt.max_longitude,
-        opt.min_latitude, opt.max_latitude,
-        longitude_interval, latitude_interval);
-
-    // open channels
-    let (geohash_tx, geohash_rx) = crossbeam_channel::unbounded();
-    let tiles = Arc::new(RwLock::new(Vec::new()));
-
-    // start worker threads
-    let mut join_handles = Vec::new();
-    for _ in 0..opt.thread_count {
-        let geohash_rx = geohash_rx.clone();
-        let tiles = tiles.clone();
-        let opt = opt.clone();
-
-        let join_handle = std::thread::spawn(move || {
-            'geohash: for geohash in geohash_rx.iter() {
-                let geohash: &String = &geohash;
-                println!("{}", geohash);
-
-                // find node responsible for this geohash
-                let node = match locate_node(&opt.ip_address,
-                        opt.port, geohash) {
-                    Ok(node) => node,
-                    Err(e) => panic!("failed to locate node: {}", e),
-                };
-
-                let end_timestamp = opt.timestamp 
-                    + (86400 - (opt.timestamp % 86400));
-
-                // retrieve sentinel-2 images
-                let sentinel2_filter = Filter {
-                    end_timestamp: Some(end_timestamp),
-                    geocode: Some(geohash.to_string()),
-                    max_cloud_coverage: None,
-                    min_pixel_coverage: Some(1.0),
-                    platform: Some("Sentinel-2".to_string()),
-                    recurse: false,
-                    source: None,
-                    start_timestamp:
-                        Some(end_timestamp - (15 * 86400) + 1),
-                };
-
-                let sentinel2_images = match get_images(&opt.album,
-                        sentinel2_filter, &node.rpc_addr) {
-                    Ok(images) => images,
-                    Err(e) => panic!("failed to get sentinel-2: {}", e),
-                };
-
-                let sentinel2_images: Vec<Image> = sentinel2_images
-                    .into_iter().filter(|x| x.files.len() == 4).collect();
-
-                // if sentinel-2 image on timestamp -> use stip
-                println!("  found {} sentinel-2 image(s)",
-                    sentinel2_images.len());
-                for image in sentinel2_images.iter() {
-                    if (image.timestamp - opt.timestamp).abs() <= 86400 {
-                        println!("    using {}", image.timestamp);
-                        let mut tiles = tiles.write().unwrap();
-                        tiles.push(
-                            Tile::Stip(node.clone(), image.clone()));
-
-                        continue 'geohash;
-                    }
-                }
+    // parse positional arguments
+    let bounds_count = if opt.polygon.is_some() { 0 } else { 4 };
+    let has_timestamp = opt.args.len() == bounds_count + 2;
+    if !has_timestamp && (opt.start.is_none()
+            || opt.args.len() != bounds_count + 1) {
+        let expected = match opt.start {
+            Some(_) => format!("{} or {}", bounds_count + 1,
+                bounds_count + 2),
+            None => (bounds_count + 2).to_string(),
+        };
 
-                // retrieve modis images
-                let modis_filter = Filter {
-                    end_timestamp: Some(end_timestamp),
-                    geocode: Some(geohash.to_string()),
-                    max_cloud_coverage: None,
-                    min_pixel_coverage: None,
-                    platform: Some("MODIS".to_string()),
-                    recurse: false,
-                    source: None,
-                    start_timestamp:
-                        Some(end_timestamp - (10 * 86400) + 1),
-                };
-
-                let modis_images = match get_images(&opt.album,
-                        modis_filter, &node.rpc_addr) {
-                    Ok(images) => images,
-                    Err(e) => panic!("failed to get modis: {}", e),
-                };
-
-                let modis_images: Vec<Image> = modis_images.into_iter()
-                    .filter(|x| x.files.len() == 2).collect();
-
-                // if two sentinel-2 images and one modis -> use SATnet
-                println!("  found {} modis image(s)",
-                    modis_images.len());
-                if sentinel2_images.len() >= 2
-                        && modis_images.len() >= 1 {
-                    let mut tiles = tiles.write().unwrap();
-                    tiles.push(Tile::Stitch(node.clone(),
-                        sentinel2_images[..2].to_vec(),
-                        modis_images[0].clone()));
-
-                    continue 'geohash;
-                }
+        clap::Error::with_description(&format!("expected {} arguments, \
+                found {}", expected, opt.args.len()),
+            clap::ErrorKind::WrongNumberOfValues).exit();
+    }
+
+    // the series start replaces TIMESTAMP, which may then be omitted
+    let timestamp: i64 = match opt.start {
+        Some(start) => start,
+        None => parse_arg("TIMESTAMP", &opt.args[bounds_count]),
+    };
+    let output_file = PathBuf::from(opt.args.last().unwrap());
+
+    // initialize area of interest
+    let (bounds, polygon) = match &opt.polygon {
+        Some(value) => match Polygon::load(value) {
+            Ok(polygon) => (polygon.bounds().clone(), Some(polygon)),
+            Err(e) => fail("failed to load polygon", e),
+        },
+        None => (Bounds {
+            min_latitude: parse_arg("MIN_LATITUDE", &opt.args[0]),
+            max_latitude: parse_arg("MAX_LATITUDE", &opt.args[1]),
+            min_longitude: parse_arg("MIN_LONGITUDE", &opt.args[2]),
+            max_longitude: parse_arg("MAX_LONGITUDE", &opt.args[3]),
+        }, None),
+    };
 
-                println!("  image unavailable");
+    // initialize tile selection policy
+    let policy: Arc<dyn SelectionPolicy> =
+            match (&opt.selection_config, opt.max_cloud_coverage) {
+        (None, None) => Arc::new(DefaultPolicy::default()),
+        (path, max_cloud_coverage) => {
+            let mut config = match path {
+                Some(path) => match SelectionConfig::from_file(path) {
+                    Ok(config) => config,
+                    Err(e) => fail("failed to load selection config",
+                        e.into()),
+                },
+                None => SelectionConfig::default(),
+            };
+
+            if max_cloud_coverage.is_some() {
+                config.sentinel2_max_cloud_coverage = max_cloud_coverage;
             }
-        });
 
-        join_handles.push(join_handle);
-    }
+            Arc::new(ConfigPolicy::new(config))
+        },
+    };
+
+    // initialize node locator
+    let rpc_addr = format!("{}:{}", opt.ip_address, opt.port);
+    let locator = NodeLocator::new(rpc_addr, opt.node_prefix_length);
+    let locator = match &opt.node_cache {
+        Some(path) => match locator
+                .with_cache_file(path, opt.node_cache_ttl) {
+            Ok(locator) => locator,
+            Err(e) => fail("failed to load node cache", e),
+        },
+        None => locator,
+    };
+
+    // initialize tile cache
+    let cache_directory = opt.cache_directory.clone().or_else(||
+        std::env::var_os("HOME")
+            .map(|x| PathBuf::from(x).join(".cache/stitch")));
+    let cache = match (opt.no_cache, cache_directory) {
+        (false, Some(directory)) => match TileCache::new(directory,
+                opt.cache_size * 1024 * 1024, opt.refresh) {
+            Ok(cache) => Some(cache),
+            Err(e) => fail("failed to initialize tile cache", e),
+        },
+        _ => None,
+    };
+
+    // initialize transfer connection settings
+    let connection = opt.connection.config();
+    let stats = connection.stats.clone();
+
+    // initialize stitchd endpoints
+    let mut endpoints = match &opt.stitchd_hosts {
+        Some(path) => match EndpointRegistry::from_hosts_file(path) {
+            Ok(endpoints) => endpoints,
+            Err(e) => fail("failed to load stitchd hosts", e.into()),
+        },
+        None => EndpointRegistry::default(),
+    };
 
-    // add geohashes to sender channel
-    for (min_long, max_long, min_lat, max_lat) in windows.iter() {
-        // compute window geohash
-        let geohash = match geocode.encode(
-                (min_long + max_long) / 2.0,
-                (min_lat + max_lat) / 2.0, 5) {
-            Ok(geohash) => geohash,
-            Err(e) => panic!("failed to compute geohash: {}", e),
+    // query stitchd capabilities, servers which cannot be described are
+    // assumed to accept any request
+    if opt.stitchd_protocol_version == Version::V1 {
+        let failures = match stitch::describe_endpoints(&mut endpoints,
+                opt.stitchd_protocol_version, &connection) {
+            Ok(failures) => failures,
+            Err(e) => fail("failed to describe stitchd servers", e),
         };
 
-        // send geohash down channel
-        if let Err(e) = geohash_tx.send(geohash) {
-            panic!("failed to send geohash: {}", e);
+        for (addr, e) in failures {
+            eprintln!("failed to describe stitchd server {}: {}", addr, e);
         }
     }
 
-    // join worker threads
-    drop(geohash_tx);
-    for join_handle in join_handles {
-        if let Err(e) = join_handle.join() {
-            panic!("failed to join worker: {:?}", e);
-        }
+    // initialize output grid
+    let warp = match (&opt.target_srs, opt.resolution, opt.size) {
+        (None, None, None) => None,
+        (target_srs, resolution, size) => Some(Warp {
+            target_srs: target_srs.clone(),
+            resolution,
+            size,
+            resampling: opt.resampling,
+        }),
+    };
+
+    // initialize reconstruction request
+    let mut request = ReconstructionRequest::new(
+            opt.album.clone(), bounds, timestamp)
+        .with_bands(opt.bands.clone())
+        .with_connection(connection)
+        .with_download_count(opt.download_count)
+        .with_endpoints(endpoints)
+        .with_geocode(opt.geocode, opt.precision)
+        .with_locator(locator)
+        .with_partial(opt.partial)
+        .with_policy(policy)
+        .with_progress(Arc::new(StderrProgress {}))
+        .with_request_count(opt.thread_count)
+        .with_stitchd_version(opt.stitchd_protocol_version);
+    if let Some(cache) = cache {
+        request = request.with_cache(cache);
     }
 
-    // download all images
-    let mut datasets = Vec::new();
-    let tiles = tiles.read().unwrap();
-    for tile in tiles.iter() {
-        let dataset = match tile.download() {
-            Ok(dataset) => dataset,
-            Err(e) => panic!("failed to download image: {}", e),
-        };
+    if let Some(polygon) = polygon {
+        request = request.with_polygon(polygon);
+    }
 
-        datasets.push(dataset);
+    if let Some(warp) = warp {
+        request = request.with_warp(warp);
     }
 
-    // merge datasets
-    let dataset = match st_image::transform::merge(&datasets) {
-        Ok(dataset) => dataset,
-        Err(e) => panic!("failed to merge datasets: {}", e),
-    };
+    let coverage_report = match opt.end {
+        Some(end_timestamp) => {
+            if timestamp > end_timestamp {
+                clap::Error::with_description(&format!("start timestamp \
+                        {} is after end timestamp {}",
+                        timestamp, end_timestamp),
+                    clap::ErrorKind::ValueValidation).exit();
+            }
 
-    // split image on provided bounds
-    let dataset = match st_image::transform::split(&dataset,
-            opt.min_longitude, opt.max_longitude,
-            opt.min_latitude, opt.max_latitude, 4326) {
-        Ok(dataset) => dataset,
-        Err(e) => panic!("failed to trim dataset: {}", e),
-    };
+            // reconstruct time series
+            let timestamps: Vec<i64> = (timestamp..=end_timestamp)
+                .step_by(opt.step.max(1) as usize * 86400).collect();
+            let results = match stitch::reconstruct_series(
+                    &request, &timestamps) {
+                Ok(results) => results,
+                Err(e) => fail("failed to reconstruct series", e),
+            };
+
+            if opt.stack {
+                // write all timestamps to a single multi-band image
+                let layers: Vec<(i64, &Dataset)> = results.iter()
+                    .map(|(timestamp, dataset, _)| (*timestamp, dataset))
+                    .collect();
+                let result = stitch::raster::stack(&layers).and_then(|x|
+                    stitch::write(&x, &output_file,
+                        opt.format, &opt.creation_options));
+                if let Err(e) = result {
+                    fail("failed to write image stack", e);
+                }
+            } else {
+                // write each timestamp to a separate image
+                for (timestamp, dataset, _) in results.iter() {
+                    let path = series_path(&output_file, *timestamp);
+                    if let Err(e) = stitch::write(dataset, &path,
+                            opt.format, &opt.creation_options) {
+                        fail("failed to write image", e);
+                    }
+                }
+            }
 
-    // open GeoTiff driver
-    let driver = match Driver::get("GTiff").compat() {
-        Ok(driver) => driver,
-        Err(e) => panic!("failed to get GTiff driver: {}", e),
-    };
+            let mut coverage_report = CoverageReport::default();
+            for (_, _, mut report) in results {
+                coverage_report.append(&mut report);
+            }
+
+            coverage_report
+        },
+        None => {
+            // reconstruct image
+            let (dataset, coverage_report) =
+                    match stitch::reconstruct_with_coverage(&request) {
+                Ok(result) => result,
+                Err(e) => fail("failed to reconstruct image", e),
+            };
+
+            // write image
+            if let Err(e) = stitch::write(&dataset, &output_file,
+                    opt.format, &opt.creation_options) {
+                fail("failed to write image", e);
+            }
 
-    /*// initialize copy options
-    let c_string = match CString::new("COMPRESS=LZW") {
-        Ok(c_string) => c_string.into_raw(),
-        Err(e) => panic!("failed to initialize c_options: {}", e),
+            coverage_report
+        },
     };
 
-    let mut c_options = vec![c_string, std::ptr::null_mut()];
+    // write coverage report alongside image
+    if opt.partial {
+        if output_file != Path::new("-") {
+            let mut path = output_file.clone().into_os_string();
+            path.push(".coverage.csv");
 
-    // write image using GeoTiff format
-    let path_str = opt.output_file.to_string_lossy();
-    if let Err(e) = dataset.create_copy(&driver, &path_str,
-            Some(c_options.as_mut_ptr())).compat() {
-        panic!("failed to copy dataset: {}", e);
+            let result = File::create(&path)
+                .and_then(|mut file| coverage_report.write(&mut file));
+            if let Err(e) = result {
+                fail("failed to write coverage report", e.into());
+            }
+        }
+
+        eprintln!("coverage: {} stip, {} imputed, {} empty",
+            coverage_report.count(Coverage::Stip),
+            coverage_report.count(Coverage::Imputed),
+            coverage_report.count(Coverage::Empty));
     }
 
-    // clean up potential memory leaks
-    unsafe {
-        for ptr in c_options {
-            if !ptr.is_null() {
-                let _ = CString::from_raw(ptr);
-            }
+    eprintln!("transfers: {} retries, {} failovers, {} relookups",
+        stats.retries(), stats.failovers(), stats.relookups());
+}
+
+/// prints reconstruction progress to stderr
+#[derive(Debug)]
+struct StderrProgress {}
+
+impl Progress for StderrProgress {
+    fn report(&self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Timestamp(timestamp) =>
+                eprintln!("timestamp {}", timestamp),
+            ProgressEvent::Skipped { timestamp, reason } =>
+                eprintln!("timestamp {}: {}", timestamp, reason),
+            ProgressEvent::ImagesFound { geohash, platform, count } =>
+                eprintln!("{}: found {} {} image(s)",
+                    geohash, count, platform),
+            ProgressEvent::StipImage { geohash, timestamp } =>
+                eprintln!("{}: using {}", geohash, timestamp),
+            ProgressEvent::Unavailable { geohash, reason } =>
+                eprintln!("{}: image unavailable, {}", geohash, reason),
+            ProgressEvent::Relookup { geohash, from, to, error } =>
+                eprintln!("{}: moved from {} to {}, retrying: {}",
+                    geohash, from, to, error),
+            ProgressEvent::DownloadFailed { geohash, error } =>
+                eprintln!("failed to download {}: {}", geohash, error),
+            ProgressEvent::Warning(message) => eprintln!("{}", message),
         }
-    }*/
-
-    // intialize copy arguments
-    let path_str = opt.output_file.to_string_lossy().to_string();
-    let c_filename = CString::new(path_str)
-        .expect("create filename CString");
-
-    let c_compress_str = CString::new("COMPRESS=LZW")
-        .expect("create 'COMPRESS=LZW' CString");
-    let c_compress_ptr = c_compress_str.into_raw();
-    let mut c_options = vec![
-        c_compress_ptr,
-        std::ptr::null_mut()
-    ];
-
-    // copy dataset using driver
-    let c_dataset = unsafe {
-        gdal_sys::GDALCreateCopy(driver.c_driver(),
-            c_filename.as_ptr(), dataset.c_dataset(), 0,
-            c_options.as_mut_ptr(), None, std::ptr::null_mut())
-    };
+    }
+}
 
-    // check for error
-    if c_dataset.is_null() {
-        let err_msg = unsafe {
-            let c_ptr = gdal_sys::CPLGetLastErrorMsg();
-            let c_str = CStr::from_ptr(c_ptr);
-            c_str.to_string_lossy().into_owned()
-        };
+fn series_path(path: &Path, timestamp: i64) -> PathBuf {
+    // a series streamed to stdout is written as consecutive datasets
+    if path == Path::new("-") {
+        return path.to_path_buf();
+    }
 
-        unsafe { gdal_sys::CPLErrorReset() };
-        panic!("failed to copy dataset: {}", err_msg);
+    let mut filename = path.file_stem().unwrap_or_default().to_os_string();
+    filename.push(format!("-{}", timestamp));
+    if let Some(extension) = path.extension() {
+        filename.push(".");
+        filename.push(extension);
     }
 
-    let _ = unsafe {
-        Dataset::from_c_dataset(c_dataset)
-    };
+    path.with_file_name(filename)
+}
 
-    // clean up c memory to mitigate leaks
-    unsafe {
-        let _ = CString::from_raw(c_compress_ptr);
+fn parse_geocode(value: &str) -> Result<Geocode, String> {
+    match value.to_lowercase().as_str() {
+        "geohash" => Ok(Geocode::Geohash),
+        "quadtile" => Ok(Geocode::QuadTile),
+        _ => Err(format!("unsupported geocode '{}'", value)),
     }
 }
 
-#[tokio::main]
-async fn get_images(album: &str, filter: Filter, rpc_address: &str)
-        -> Result<Vec<Image>, Box<dyn Error>> {
-    // initialize ImageManagement grpc client
-    let mut client = ImageManagementClient::connect(
-        format!("http://{}", rpc_address)).await?;
-
-    // initialize ImageListRequest
-    let request = ImageListRequest {
-        album: album.to_string(),
-        filter: filter,
-    };
+fn parse_arg<T: FromStr>(name: &str, value: &str) -> T
+        where T::Err: Display {
+    match value.parse::<T>() {
+        Ok(value) => value,
+        Err(e) => clap::Error::with_description(&format!(
+                "invalid {} '{}': {}", name, value, e),
+            clap::ErrorKind::InvalidValue).exit(),
+    }
+}
 
-    // iterate over image stream
-    let mut stream = client.list(Request::new(request.clone()))
-        .await?.into_inner();
+fn parse_resolution(value: &str) -> Result<(f64, f64), String> {
+    let values = value.split(',').map(|x| x.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|e| format!("invalid resolution '{}': {}", value, e))?;
 
-    let mut images = Vec::new();
-    while let Some(image) = stream.message().await? {
-        images.push(image);
+    match values.as_slice() {
+        [size] if *size > 0.0 => Ok((*size, *size)),
+        [x, y] if *x > 0.0 && *y > 0.0 => Ok((*x, *y)),
+        _ => Err(format!("invalid resolution '{}'", value)),
     }
-
-    // sort in descending order by timstamp
-    images.sort_by(|a, b| b.timestamp.partial_cmp(&a.timestamp).unwrap());
-    Ok(images)
 }
 
-#[tokio::main]
-async fn locate_node(ip_address: &IpAddr, port: u16,
-        geohash: &str) -> Result<Node, Box<dyn Error>> {
-    // initialize NodeManagement grpc client
-    let mut client = NodeManagementClient::connect(
-        format!("http://{}:{}", ip_address, port)).await?;
-
-    // initialize NodeLocateRequest
-    let request = Request::new(NodeLocateRequest {
-        geocode: geohash.to_string(),
-    });
-
-    // retrieve NodeLocateReply
-    let reply = client.locate(request).await?;
-    let reply = reply.get_ref();
-
-    // process node
-    match &reply.node {
-        Some(node) => Ok(node.clone()),
-        None => Err(format!("failed to locate geocode '{}'",
-            geohash).into()),
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let values = value.split(',').map(|x| x.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|e| format!("invalid size '{}': {}", value, e))?;
+
+    match values.as_slice() {
+        [width, height] if *width > 0 && *height > 0 =>
+            Ok((*width, *height)),
+        _ => Err(format!("invalid size '{}'", value)),
     }
 }
+
+fn fail(message: &str, err: StitchError) -> ! {
+    eprintln!("{}: {}", message, err);
+
+    // exit with an error category specific code
+    let code = match err {
+        StitchError::NoImagery(_) => 2,
+        StitchError::NodeLocate(_) => 3,
+        StitchError::Transport(_) | StitchError::Rpc(_) => 4,
+        StitchError::Io(_) => 5,
+        StitchError::Remote(_) => 6,
+        StitchError::Deserialize(_) => 7,
+        StitchError::Geocode(_) => 8,
+        StitchError::Gdal(_) => 9,
+        StitchError::Config(_) => 10,
+        StitchError::MissingBands(_) => 11,
+        StitchError::Internal(_) => 1,
+    };
+
+    std::process::exit(code);
+}
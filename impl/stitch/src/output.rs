@@ -0,0 +1,187 @@
+use failure::ResultExt;
+use gdal::{Dataset, Driver};
+
+use crate::StitchError;
+use crate::raster;
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    GTiff,
+    /// cloud-optimized geotiff, tiled with internal overviews
+    Cog,
+    NetCdf,
+    Png,
+    Jpeg,
+    /// st_image::serialize stream, written to stdout if the path is '-'
+    Serialize,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "gtiff" | "geotiff" => Ok(Format::GTiff),
+            "cog" => Ok(Format::Cog),
+            "netcdf" => Ok(Format::NetCdf),
+            "png" => Ok(Format::Png),
+            "jpeg" | "jpg" => Ok(Format::Jpeg),
+            "serialize" => Ok(Format::Serialize),
+            _ => Err(format!("unsupported format '{}'", s)),
+        }
+    }
+}
+
+/// write dataset in the provided format, passing 'KEY=VALUE' creation
+/// options through to the gdal driver
+pub fn write(dataset: &Dataset, path: &Path, format: Format,
+        creation_options: &[String]) -> Result<(), StitchError> {
+    match format {
+        Format::GTiff if creation_options.is_empty() => create_copy(
+            "GTiff", dataset, path, &["COMPRESS=LZW".to_string()]),
+        Format::GTiff =>
+            create_copy("GTiff", dataset, path, creation_options),
+        Format::Cog => write_cog(dataset, path, creation_options),
+        Format::NetCdf =>
+            create_copy("netCDF", dataset, path, creation_options),
+        Format::Png => write_image("PNG", dataset, path, creation_options),
+        Format::Jpeg =>
+            write_image("JPEG", dataset, path, creation_options),
+        Format::Serialize => {
+            let result = if path == Path::new("-") {
+                let stdout = std::io::stdout();
+                let mut writer = BufWriter::new(stdout.lock());
+                st_image::serialize::write(dataset, &mut writer)
+                    .and_then(|_| writer.flush().map_err(|e| e.into()))
+            } else {
+                let mut writer = BufWriter::new(File::create(path)?);
+                st_image::serialize::write(dataset, &mut writer)
+                    .and_then(|_| writer.flush().map_err(|e| e.into()))
+            };
+
+            result.map_err(|e| StitchError::Internal(
+                format!("failed to serialize dataset: {}", e)))
+        },
+    }
+}
+
+fn write_cog(dataset: &Dataset, path: &Path,
+        creation_options: &[String]) -> Result<(), StitchError> {
+    // overviews are built on an in-memory copy to leave dataset intact
+    let driver = Driver::get("MEM").compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    let c_filename = CString::new("").unwrap();
+    let c_dataset = unsafe {
+        gdal_sys::GDALCreateCopy(driver.c_driver(), c_filename.as_ptr(),
+            dataset.c_dataset(), 0, std::ptr::null_mut(),
+            None, std::ptr::null_mut())
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(format!(
+            "failed to copy dataset: {}", raster::last_error())));
+    }
+
+    let copy = unsafe { Dataset::from_c_dataset(c_dataset) };
+
+    // halve resolution until overviews fit within a single tile
+    let (width, height) = copy.size();
+    let mut overviews = Vec::new();
+    let mut factor = 2;
+    while width / factor >= 256 || height / factor >= 256 {
+        overviews.push(factor as i32);
+        factor *= 2;
+    }
+
+    if !overviews.is_empty() {
+        let c_resampling = CString::new("AVERAGE").unwrap();
+        let result = unsafe {
+            gdal_sys::GDALBuildOverviews(c_dataset, c_resampling.as_ptr(),
+                overviews.len() as i32, overviews.as_mut_ptr(), 0,
+                std::ptr::null_mut(), None, std::ptr::null_mut())
+        };
+
+        if result != gdal_sys::CPLErr::CE_None {
+            return Err(StitchError::Gdal(format!(
+                "failed to build overviews: {}", raster::last_error())));
+        }
+    }
+
+    // copy tiles and overviews into a geotiff
+    let mut options = vec!["TILED=YES".to_string(),
+        "COPY_SRC_OVERVIEWS=YES".to_string()];
+    if creation_options.is_empty() {
+        options.push("COMPRESS=LZW".to_string());
+    } else {
+        options.extend(creation_options.iter().cloned());
+    }
+
+    create_copy("GTiff", &copy, path, &options)
+}
+
+fn write_image(driver_name: &str, dataset: &Dataset, path: &Path,
+        creation_options: &[String]) -> Result<(), StitchError> {
+    // images hold grayscale, rgb, or rgba 8-bit pixels
+    let band_count = dataset.count();
+    if ![1, 3, 4].contains(&band_count) {
+        return Err(StitchError::Config(format!(
+            "{} output requires 1, 3 or 4 bands, dataset has {}",
+            driver_name, band_count)));
+    }
+
+    if raster::is_byte(dataset) {
+        return create_copy(driver_name, dataset, path, creation_options);
+    }
+
+    let dataset = raster::scale_to_byte(dataset)?;
+    create_copy(driver_name, &dataset, path, creation_options)
+}
+
+fn create_copy(driver_name: &str, dataset: &Dataset, path: &Path,
+        creation_options: &[String]) -> Result<(), StitchError> {
+    // open driver
+    let driver = Driver::get(driver_name).compat().map_err(|e|
+        StitchError::Gdal(format!("failed to get {} driver: {}",
+            driver_name, e)))?;
+
+    // intialize copy arguments
+    let path_str = path.to_string_lossy().to_string();
+    let c_filename = CString::new(path_str).map_err(|e|
+        StitchError::Gdal(format!("invalid output filename: {}", e)))?;
+
+    let mut c_options_str = Vec::new();
+    for option in creation_options.iter() {
+        let c_option = CString::new(option.as_str()).map_err(|e|
+            StitchError::Gdal(format!("invalid creation option: {}", e)))?;
+        c_options_str.push(c_option);
+    }
+
+    let mut c_options: Vec<*mut std::os::raw::c_char> = c_options_str
+        .iter().map(|x| x.as_ptr() as *mut _).collect();
+    c_options.push(std::ptr::null_mut());
+
+    // copy dataset using driver
+    let c_dataset = unsafe {
+        gdal_sys::GDALCreateCopy(driver.c_driver(),
+            c_filename.as_ptr(), dataset.c_dataset(), 0,
+            c_options.as_mut_ptr(), None, std::ptr::null_mut())
+    };
+
+    // check for error
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(format!(
+            "failed to copy dataset: {}", raster::last_error())));
+    }
+
+    let _ = unsafe {
+        Dataset::from_c_dataset(c_dataset)
+    };
+
+    Ok(())
+}
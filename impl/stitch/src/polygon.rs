@@ -0,0 +1,430 @@
+use failure::ResultExt;
+use gdal::{Dataset, Driver};
+
+use crate::{Bounds, StitchError, Window};
+use crate::raster::{self, SpatialRef};
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+
+const GDAL_OF_VECTOR: u32 = 0x04;
+
+/// area of interest, stored as a wgs84 longitude / latitude multipolygon
+#[derive(Clone, Debug)]
+pub struct Polygon {
+    bounds: Bounds,
+    wkt: String,
+}
+
+impl Polygon {
+    /// parse a GeoJSON or WKT polygon, either inline or from a file
+    pub fn load(value: &str) -> Result<Polygon, StitchError> {
+        // gdal opens vector files and inline geojson alike
+        let geometry = if Path::new(value).exists()
+                || value.trim_start().starts_with('{') {
+            read_features(value)?
+        } else {
+            from_wkt(value)?
+        };
+
+        // normalize to a multipolygon
+        let geometry = Geometry(unsafe {
+            gdal_sys::OGR_G_ForceToMultiPolygon(geometry.into_raw())
+        });
+
+        let geometry_type = unsafe { gdal_sys::OGR_GT_Flatten(
+            gdal_sys::OGR_G_GetGeometryType(geometry.0)) };
+        if geometry_type != gdal_sys::OGRwkbGeometryType::wkbMultiPolygon
+                || unsafe { gdal_sys::OGR_G_IsEmpty(geometry.0) } != 0 {
+            return Err(StitchError::Config(
+                "area of interest must contain a polygon".to_string()));
+        }
+
+        // compute bounding box
+        let mut envelope = gdal_sys::OGREnvelope {
+            MinX: 0.0, MaxX: 0.0, MinY: 0.0, MaxY: 0.0 };
+        unsafe { gdal_sys::OGR_G_GetEnvelope(geometry.0, &mut envelope) };
+
+        Ok(Polygon {
+            bounds: Bounds {
+                min_latitude: envelope.MinY,
+                max_latitude: envelope.MaxY,
+                min_longitude: envelope.MinX,
+                max_longitude: envelope.MaxX,
+            },
+            wkt: geometry.to_wkt()?,
+        })
+    }
+
+    pub fn bounds(&self) -> &Bounds {
+        &self.bounds
+    }
+
+    /// retain windows which intersect the polygon
+    pub fn filter(&self, windows: &[Window])
+            -> Result<Vec<Window>, StitchError> {
+        let geometry = from_wkt(&self.wkt)?;
+
+        let mut intersecting = Vec::new();
+        for window in windows.iter() {
+            let (min_long, max_long, min_lat, max_lat) = window;
+            let rectangle = from_wkt(&format!(
+                "POLYGON (({0} {2},{1} {2},{1} {3},{0} {3},{0} {2}))",
+                min_long, max_long, min_lat, max_lat))?;
+
+            if unsafe { gdal_sys::OGR_G_Intersects(
+                    geometry.0, rectangle.0) } != 0 {
+                intersecting.push(*window);
+            }
+        }
+
+        Ok(intersecting)
+    }
+
+    /// set pixels outside the polygon to the no-data value
+    pub fn mask(&self, dataset: &Dataset) -> Result<(), StitchError> {
+        // transform polygon into the dataset projection
+        let geometry = from_wkt(&self.wkt)?;
+        let wgs84 = SpatialRef::new("EPSG:4326")?;
+        let projection = SpatialRef::new(&dataset.projection())?;
+        let result = unsafe {
+            gdal_sys::OGR_G_AssignSpatialReference(geometry.0, wgs84.0);
+            gdal_sys::OGR_G_TransformTo(geometry.0, projection.0)
+        };
+
+        if result != gdal_sys::OGRErr::OGRERR_NONE {
+            return Err(StitchError::Gdal(format!(
+                "failed to transform polygon: {}", raster::last_error())));
+        }
+
+        // rasterize polygon into an in-memory mask
+        let (width, height) = dataset.size();
+        let driver = Driver::get("MEM").compat()
+            .map_err(|e| StitchError::Gdal(e.to_string()))?;
+        let c_filename = CString::new("").unwrap();
+        let c_mask = unsafe {
+            gdal_sys::GDALCreate(driver.c_driver(), c_filename.as_ptr(),
+                width as i32, height as i32, 1,
+                gdal_sys::GDALDataType::GDT_Byte, std::ptr::null_mut())
+        };
+
+        if c_mask.is_null() {
+            return Err(StitchError::Gdal(raster::last_error()));
+        }
+
+        let mask = unsafe { Dataset::from_c_dataset(c_mask) };
+        let transform = dataset.geo_transform().compat()
+            .map_err(|e| StitchError::Gdal(e.to_string()))?;
+        mask.set_geo_transform(&transform).compat()
+            .map_err(|e| StitchError::Gdal(e.to_string()))?;
+
+        let mut mask_buf = vec![0u8; width * height];
+        let result = unsafe {
+            let mut band_list = [1];
+            let mut c_geometries = [geometry.0];
+            let mut burn_values = [1.0];
+            let result = gdal_sys::GDALRasterizeGeometries(c_mask,
+                1, band_list.as_mut_ptr(), 1, c_geometries.as_mut_ptr(),
+                None, std::ptr::null_mut(), burn_values.as_mut_ptr(),
+                std::ptr::null_mut(), None, std::ptr::null_mut());
+
+            if result != gdal_sys::CPLErr::CE_None {
+                result
+            } else {
+                gdal_sys::GDALRasterIO(
+                    gdal_sys::GDALGetRasterBand(c_mask, 1),
+                    gdal_sys::GDALRWFlag::GF_Read, 0, 0,
+                    width as i32, height as i32,
+                    mask_buf.as_mut_ptr() as *mut c_void,
+                    width as i32, height as i32,
+                    gdal_sys::GDALDataType::GDT_Byte, 0, 0)
+            }
+        };
+
+        if result != gdal_sys::CPLErr::CE_None {
+            return Err(StitchError::Gdal(format!(
+                "failed to rasterize polygon: {}", raster::last_error())));
+        }
+
+        // fill masked pixels in each band
+        let mut buf = vec![0f64; width * height];
+        for i in 0..dataset.count() as i32 {
+            let result = unsafe {
+                let c_band = gdal_sys::GDALGetRasterBand(
+                    dataset.c_dataset(), i + 1);
+
+                let mut has_no_data = 0;
+                let mut no_data_value = gdal_sys::GDALGetRasterNoDataValue(
+                    c_band, &mut has_no_data);
+                if has_no_data == 0 {
+                    no_data_value = 0.0;
+                    gdal_sys::GDALSetRasterNoDataValue(c_band, no_data_value);
+                }
+
+                let read = gdal_sys::GDALRasterIO(c_band,
+                    gdal_sys::GDALRWFlag::GF_Read, 0, 0,
+                    width as i32, height as i32,
+                    buf.as_mut_ptr() as *mut c_void,
+                    width as i32, height as i32,
+                    gdal_sys::GDALDataType::GDT_Float64, 0, 0);
+                if read != gdal_sys::CPLErr::CE_None {
+                    read
+                } else {
+                    for (value, inside) in buf.iter_mut().zip(&mask_buf) {
+                        if *inside == 0 {
+                            *value = no_data_value;
+                        }
+                    }
+
+                    gdal_sys::GDALRasterIO(c_band,
+                        gdal_sys::GDALRWFlag::GF_Write, 0, 0,
+                        width as i32, height as i32,
+                        buf.as_mut_ptr() as *mut c_void,
+                        width as i32, height as i32,
+                        gdal_sys::GDALDataType::GDT_Float64, 0, 0)
+                }
+            };
+
+            if result != gdal_sys::CPLErr::CE_None {
+                return Err(StitchError::Gdal(raster::last_error()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// owned ogr geometry handle
+struct Geometry(gdal_sys::OGRGeometryH);
+
+impl Geometry {
+    fn into_raw(self) -> gdal_sys::OGRGeometryH {
+        let c_geometry = self.0;
+        std::mem::forget(self);
+        c_geometry
+    }
+
+    fn to_wkt(&self) -> Result<String, StitchError> {
+        let mut c_wkt: *mut c_char = std::ptr::null_mut();
+        let result = unsafe {
+            gdal_sys::OGR_G_ExportToWkt(self.0, &mut c_wkt) };
+        if result != gdal_sys::OGRErr::OGRERR_NONE || c_wkt.is_null() {
+            return Err(StitchError::Gdal(format!(
+                "failed to export polygon: {}", raster::last_error())));
+        }
+
+        let wkt = unsafe {
+            let wkt = CStr::from_ptr(c_wkt).to_string_lossy().into_owned();
+            gdal_sys::VSIFree(c_wkt as *mut c_void);
+            wkt
+        };
+
+        Ok(wkt)
+    }
+}
+
+impl Drop for Geometry {
+    fn drop(&mut self) {
+        unsafe { gdal_sys::OGR_G_DestroyGeometry(self.0) };
+    }
+}
+
+fn from_wkt(wkt: &str) -> Result<Geometry, StitchError> {
+    let c_wkt = CString::new(wkt).map_err(|e|
+        StitchError::Config(format!("invalid polygon wkt: {}", e)))?;
+
+    let mut c_wkt_ptr = c_wkt.as_ptr() as *mut c_char;
+    let mut c_geometry = std::ptr::null_mut();
+    let result = unsafe { gdal_sys::OGR_G_CreateFromWkt(&mut c_wkt_ptr,
+        std::ptr::null_mut(), &mut c_geometry) };
+
+    if result != gdal_sys::OGRErr::OGRERR_NONE || c_geometry.is_null() {
+        return Err(StitchError::Config(
+            format!("failed to parse polygon wkt '{}'", wkt)));
+    }
+
+    Ok(Geometry(c_geometry))
+}
+
+fn read_features(value: &str) -> Result<Geometry, StitchError> {
+    let c_value = CString::new(value).map_err(|e|
+        StitchError::Config(format!("invalid polygon: {}", e)))?;
+
+    let c_dataset = unsafe {
+        gdal_sys::GDALOpenEx(c_value.as_ptr(), GDAL_OF_VECTOR,
+            std::ptr::null(), std::ptr::null(), std::ptr::null())
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Config(format!(
+            "failed to open polygon: {}", raster::last_error())));
+    }
+
+    let result = union_features(c_dataset);
+    unsafe { gdal_sys::GDALClose(c_dataset) };
+
+    match result? {
+        Some(geometry) => Ok(geometry),
+        None => Err(StitchError::Config(
+            "polygon contains no features".to_string())),
+    }
+}
+
+fn union_features(c_dataset: gdal_sys::GDALDatasetH)
+        -> Result<Option<Geometry>, StitchError> {
+    let wgs84 = SpatialRef::new("EPSG:4326")?;
+
+    let mut union: Option<Geometry> = None;
+    let layer_count = unsafe {
+        gdal_sys::GDALDatasetGetLayerCount(c_dataset) };
+    for i in 0..layer_count {
+        let (c_layer, c_srs) = unsafe {
+            let c_layer = gdal_sys::GDALDatasetGetLayer(c_dataset, i);
+            gdal_sys::OGR_L_ResetReading(c_layer);
+            (c_layer, gdal_sys::OGR_L_GetSpatialRef(c_layer))
+        };
+
+        loop {
+            let c_feature = unsafe {
+                gdal_sys::OGR_L_GetNextFeature(c_layer) };
+            if c_feature.is_null() {
+                break;
+            }
+
+            // copy the feature geometry before releasing the feature
+            let c_geometry = unsafe {
+                let c_geometry = gdal_sys::OGR_F_GetGeometryRef(c_feature);
+                let c_geometry = if c_geometry.is_null() {
+                    c_geometry
+                } else {
+                    gdal_sys::OGR_G_Clone(c_geometry)
+                };
+
+                gdal_sys::OGR_F_Destroy(c_feature);
+                c_geometry
+            };
+
+            if c_geometry.is_null() {
+                continue;
+            }
+
+            // convert features to wgs84 longitude / latitude
+            let geometry = Geometry(c_geometry);
+            if !c_srs.is_null() {
+                let result = unsafe {
+                    gdal_sys::OGR_G_AssignSpatialReference(
+                        geometry.0, c_srs);
+                    gdal_sys::OGR_G_TransformTo(geometry.0, wgs84.0)
+                };
+
+                if result != gdal_sys::OGRErr::OGRERR_NONE {
+                    return Err(StitchError::Gdal(format!(
+                        "failed to transform polygon: {}",
+                        raster::last_error())));
+                }
+            }
+
+            union = match union {
+                Some(union) => {
+                    let c_union = unsafe {
+                        gdal_sys::OGR_G_Union(union.0, geometry.0) };
+                    if c_union.is_null() {
+                        return Err(StitchError::Gdal(format!(
+                            "failed to union polygons: {}",
+                            raster::last_error())));
+                    }
+
+                    Some(Geometry(c_union))
+                },
+                None => Some(geometry),
+            };
+        }
+    }
+
+    Ok(union)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raster::tests::dataset;
+
+    use super::*;
+
+    const TRIANGLE: &str = "POLYGON ((-105.1 40.4, -105.0 40.4, \
+        -105.05 40.5, -105.1 40.4))";
+
+    fn pixel(dataset: &Dataset, x: i32, y: i32) -> u8 {
+        let mut value = 0u8;
+        let result = unsafe {
+            gdal_sys::GDALRasterIO(
+                gdal_sys::GDALGetRasterBand(dataset.c_dataset(), 1),
+                gdal_sys::GDALRWFlag::GF_Read, x, y, 1, 1,
+                &mut value as *mut u8 as *mut c_void, 1, 1,
+                gdal_sys::GDALDataType::GDT_Byte, 0, 0)
+        };
+
+        assert_eq!(result, gdal_sys::CPLErr::CE_None);
+        value
+    }
+
+    #[test]
+    fn load_wkt() {
+        let polygon = Polygon::load(TRIANGLE).unwrap();
+        let bounds = polygon.bounds();
+        assert_eq!((bounds.min_longitude, bounds.max_longitude),
+            (-105.1, -105.0));
+        assert_eq!((bounds.min_latitude, bounds.max_latitude),
+            (40.4, 40.5));
+
+        assert!(Polygon::load("POINT (-105.05 40.45)").is_err());
+        assert!(Polygon::load("POLYGON ((-105.1 40.4").is_err());
+    }
+
+    #[test]
+    fn filter_windows() {
+        let polygon = Polygon::load(TRIANGLE).unwrap();
+        let windows = [
+            // inside the triangle
+            (-105.06, -105.04, 40.41, 40.43),
+            // within the bounding box, but beside the apex
+            (-105.1, -105.09, 40.49, 40.5),
+            // straddling the left edge
+            (-105.1, -105.07, 40.43, 40.46),
+            // outside the bounding box
+            (-104.9, -104.8, 40.4, 40.5),
+        ];
+
+        assert_eq!(polygon.filter(&windows).unwrap(),
+            vec![windows[0], windows[2]]);
+    }
+
+    #[test]
+    fn mask_reprojected() {
+        // 100m utm zone 13n grid covering the triangle bounding box
+        let transform = [491500.0, 100.0, 0.0, 4483300.0, 0.0, -100.0];
+        let dataset = dataset("EPSG:32613", transform, 85, 112, 1);
+        unsafe {
+            let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), 1);
+            gdal_sys::GDALFillRaster(c_band, 1.0, 0.0);
+        }
+
+        let polygon = Polygon::load(TRIANGLE).unwrap();
+        polygon.mask(&dataset).unwrap();
+
+        // (-105.05, 40.45) lies near pixel (42, 56), the upper corners and
+        // the lower corners beyond the triangle vertices fall outside
+        assert_eq!(pixel(&dataset, 42, 56), 1);
+        assert_eq!(pixel(&dataset, 42, 100), 1);
+        assert_eq!(pixel(&dataset, 0, 0), 0);
+        assert_eq!(pixel(&dataset, 84, 0), 0);
+        assert_eq!(pixel(&dataset, 10, 10), 0);
+
+        let mut has_no_data = 0;
+        let no_data_value = unsafe {
+            gdal_sys::GDALGetRasterNoDataValue(gdal_sys::GDALGetRasterBand(
+                dataset.c_dataset(), 1), &mut has_no_data)
+        };
+        assert_eq!((has_no_data, no_data_value), (1, 0.0));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::StitchError;
+
+use std::fmt::Debug;
+
+/// reconstruction progress, reported as tiles are selected and downloaded
+#[derive(Debug)]
+pub enum ProgressEvent<'a> {
+    /// a series reconstruction started a timestamp
+    Timestamp(i64),
+    /// a series timestamp produced no image
+    Skipped { timestamp: i64, reason: &'a str },
+    /// candidate images of a platform found for a geohash
+    ImagesFound { geohash: &'a str, platform: &'a str, count: usize },
+    /// a sentinel-2 image is downloaded directly from stip
+    StipImage { geohash: &'a str, timestamp: i64 },
+    /// no tile is available for a geohash
+    Unavailable { geohash: &'a str, reason: &'a str },
+    /// a geohash moved to another node and its download is retried
+    Relookup {
+        geohash: &'a str,
+        from: &'a str,
+        to: &'a str,
+        error: &'a StitchError,
+    },
+    /// a partial reconstruction fills a failed download with no-data
+    DownloadFailed { geohash: &'a str, error: &'a StitchError },
+    /// a failure which does not affect the reconstructed image
+    Warning(String),
+}
+
+pub trait Progress: Debug + Send + Sync {
+    fn report(&self, event: ProgressEvent);
+}
+
+/// discards all progress events
+#[derive(Clone, Debug, Default)]
+pub struct SilentProgress {}
+
+impl Progress for SilentProgress {
+    fn report(&self, _event: ProgressEvent) {}
+}
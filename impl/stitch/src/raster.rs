@@ -0,0 +1,672 @@
+use failure::ResultExt;
+use gdal::{Dataset, Driver};
+
+use crate::StitchError;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_void;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resampling {
+    Nearest,
+    Bilinear,
+    Cubic,
+    CubicSpline,
+    Lanczos,
+    Average,
+    Mode,
+}
+
+impl Resampling {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Resampling::Nearest => "near",
+            Resampling::Bilinear => "bilinear",
+            Resampling::Cubic => "cubic",
+            Resampling::CubicSpline => "cubicspline",
+            Resampling::Lanczos => "lanczos",
+            Resampling::Average => "average",
+            Resampling::Mode => "mode",
+        }
+    }
+}
+
+impl FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Resampling, String> {
+        match s.to_lowercase().as_str() {
+            "near" | "nearest" => Ok(Resampling::Nearest),
+            "bilinear" => Ok(Resampling::Bilinear),
+            "cubic" => Ok(Resampling::Cubic),
+            "cubicspline" => Ok(Resampling::CubicSpline),
+            "lanczos" => Ok(Resampling::Lanczos),
+            "average" => Ok(Resampling::Average),
+            "mode" => Ok(Resampling::Mode),
+            _ => Err(format!("unsupported resampling method '{}'", s)),
+        }
+    }
+}
+
+/// output projection and pixel grid for reconstructed datasets
+#[derive(Clone, Debug)]
+pub struct Warp {
+    /// any srs definition gdal accepts (ex. 'EPSG:32613')
+    pub target_srs: Option<String>,
+    /// pixel size in target srs units, aligned to multiples of itself
+    pub resolution: Option<(f64, f64)>,
+    /// output width and height in pixels
+    pub size: Option<(u32, u32)>,
+    pub resampling: Resampling,
+}
+
+/// create a no-data dataset on the template grid covering a wgs84
+/// longitude / latitude window
+pub fn empty_dataset(template: &Dataset, min_x: f64, max_x: f64,
+        min_y: f64, max_y: f64) -> Result<Dataset, StitchError> {
+    // project window corners into the template srs
+    let wgs84 = SpatialRef::new("EPSG:4326")?;
+    let projection = SpatialRef::new(&template.projection())?;
+    let mut xs = [min_x, min_x, max_x, max_x];
+    let mut ys = [min_y, max_y, min_y, max_y];
+    transform_points(&wgs84, &projection, &mut xs, &mut ys)?;
+
+    // compute extent and dimensions using the template pixel grid
+    let transform = template.geo_transform().compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    let (geo_transform, width, height) = grid_extent(&transform, &xs, &ys);
+    let band_count = template.count() as i32;
+
+    // read template band type and no-data value
+    let (band_type, no_data_value) = unsafe {
+        let c_band = gdal_sys::GDALGetRasterBand(template.c_dataset(), 1);
+        if c_band.is_null() {
+            return Err(StitchError::Gdal(last_error()));
+        }
+
+        let mut has_no_data = 0;
+        let no_data_value = gdal_sys::GDALGetRasterNoDataValue(
+            c_band, &mut has_no_data);
+        (gdal_sys::GDALGetRasterDataType(c_band),
+            if has_no_data != 0 { no_data_value } else { 0.0 })
+    };
+
+    // create in-memory dataset
+    let driver = Driver::get("MEM").compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    let c_filename = CString::new("").unwrap();
+    let c_dataset = unsafe {
+        gdal_sys::GDALCreate(driver.c_driver(), c_filename.as_ptr(),
+            width, height, band_count, band_type, std::ptr::null_mut())
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(last_error()));
+    }
+
+    let dataset = unsafe { Dataset::from_c_dataset(c_dataset) };
+    dataset.set_geo_transform(&geo_transform).compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    dataset.set_projection(&template.projection()).compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+
+    // fill bands with no-data value
+    for i in 0..band_count {
+        unsafe {
+            let c_band = gdal_sys::GDALGetRasterBand(c_dataset, i + 1);
+            gdal_sys::GDALSetRasterNoDataValue(c_band, no_data_value);
+            gdal_sys::GDALFillRaster(c_band, no_data_value, 0.0);
+        }
+    }
+
+    Ok(dataset)
+}
+
+/// geotransform and dimensions of the smallest region of a north-up grid
+/// containing all points
+fn grid_extent(transform: &[f64; 6], xs: &[f64], ys: &[f64])
+        -> ([f64; 6], i32, i32) {
+    let min_x = xs.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max_x = xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let min_y = ys.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max_y = ys.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    // snap outward to pixel edges, rows increase as y decreases
+    let left = ((min_x - transform[0]) / transform[1]).floor();
+    let right = ((max_x - transform[0]) / transform[1]).ceil();
+    let top = ((max_y - transform[3]) / transform[5]).floor();
+    let bottom = ((min_y - transform[3]) / transform[5]).ceil();
+
+    let geo_transform = [transform[0] + left * transform[1], transform[1],
+        0.0, transform[3] + top * transform[5], 0.0, transform[5]];
+    (geo_transform, (right - left) as i32, (bottom - top) as i32)
+}
+
+/// transform points between spatial reference systems in place
+fn transform_points(src: &SpatialRef, dst: &SpatialRef,
+        xs: &mut [f64], ys: &mut [f64]) -> Result<(), StitchError> {
+    let result = unsafe {
+        let c_transform =
+            gdal_sys::OCTNewCoordinateTransformation(src.0, dst.0);
+        if c_transform.is_null() {
+            return Err(StitchError::Gdal(format!(
+                "failed to create transformation: {}", last_error())));
+        }
+
+        let result = gdal_sys::OCTTransform(c_transform, xs.len() as i32,
+            xs.as_mut_ptr(), ys.as_mut_ptr(), std::ptr::null_mut());
+        gdal_sys::OCTDestroyCoordinateTransformation(c_transform);
+        result
+    };
+
+    if result == 0 {
+        return Err(StitchError::Gdal(format!(
+            "failed to transform points: {}", last_error())));
+    }
+
+    Ok(())
+}
+
+pub fn stack(layers: &[(i64, &Dataset)])
+        -> Result<Dataset, StitchError> {
+    let (_, template) = match layers.first() {
+        Some(layer) => layer,
+        None => return Err(StitchError::NoImagery(
+            "no datasets to stack".to_string())),
+    };
+
+    // validate all layers share dimensions
+    let (width, height) = template.size();
+    let band_count = template.count() as i32;
+    for (timestamp, dataset) in layers.iter() {
+        if dataset.size() != (width, height)
+                || dataset.count() as i32 != band_count {
+            return Err(StitchError::Gdal(format!(
+                "dataset dimensions for timestamp {} differ", timestamp)));
+        }
+    }
+
+    let band_type = unsafe {
+        let c_band = gdal_sys::GDALGetRasterBand(template.c_dataset(), 1);
+        if c_band.is_null() {
+            return Err(StitchError::Gdal(last_error()));
+        }
+
+        gdal_sys::GDALGetRasterDataType(c_band)
+    };
+
+    // create in-memory dataset
+    let driver = Driver::get("MEM").compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    let c_filename = CString::new("").unwrap();
+    let c_dataset = unsafe {
+        gdal_sys::GDALCreate(driver.c_driver(), c_filename.as_ptr(),
+            width as i32, height as i32, band_count * layers.len() as i32,
+            band_type, std::ptr::null_mut())
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(last_error()));
+    }
+
+    let dataset = unsafe { Dataset::from_c_dataset(c_dataset) };
+    let transform = template.geo_transform().compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    dataset.set_geo_transform(&transform).compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    dataset.set_projection(&template.projection()).compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+
+    // copy bands, recording the timestamp in band metadata
+    let type_size = unsafe { gdal_sys::GDALGetDataTypeSizeBytes(band_type) };
+    let mut buf = vec![0u8; width * height * type_size as usize];
+    let c_key = CString::new("TIMESTAMP").unwrap();
+    for (i, (timestamp, layer)) in layers.iter().enumerate() {
+        let c_timestamp = CString::new(timestamp.to_string()).unwrap();
+        for j in 0..band_count {
+            let c_description = CString::new(
+                format!("{} band {}", timestamp, j + 1)).unwrap();
+
+            let result = unsafe {
+                let c_src = gdal_sys::GDALGetRasterBand(
+                    layer.c_dataset(), j + 1);
+                let c_dst = gdal_sys::GDALGetRasterBand(c_dataset,
+                    (i as i32 * band_count) + j + 1);
+
+                let mut has_no_data = 0;
+                let no_data_value = gdal_sys::GDALGetRasterNoDataValue(
+                    c_src, &mut has_no_data);
+                if has_no_data != 0 {
+                    gdal_sys::GDALSetRasterNoDataValue(c_dst, no_data_value);
+                }
+
+                gdal_sys::GDALSetMetadataItem(c_dst, c_key.as_ptr(),
+                    c_timestamp.as_ptr(), std::ptr::null());
+                gdal_sys::GDALSetDescription(c_dst, c_description.as_ptr());
+
+                let read = gdal_sys::GDALRasterIO(c_src,
+                    gdal_sys::GDALRWFlag::GF_Read, 0, 0,
+                    width as i32, height as i32,
+                    buf.as_mut_ptr() as *mut c_void,
+                    width as i32, height as i32, band_type, 0, 0);
+                if read != gdal_sys::CPLErr::CE_None {
+                    read
+                } else {
+                    gdal_sys::GDALRasterIO(c_dst,
+                        gdal_sys::GDALRWFlag::GF_Write, 0, 0,
+                        width as i32, height as i32,
+                        buf.as_mut_ptr() as *mut c_void,
+                        width as i32, height as i32, band_type, 0, 0)
+                }
+            };
+
+            if result != gdal_sys::CPLErr::CE_None {
+                return Err(StitchError::Gdal(last_error()));
+            }
+        }
+    }
+
+    Ok(dataset)
+}
+
+/// combine bands of datasets covering the same extent onto the finest of
+/// their grids, given the (dataset index, 1-based band) of each output band
+pub fn select_bands(datasets: &[Dataset], bands: &[(usize, i32)])
+        -> Result<Dataset, StitchError> {
+    let mut transforms = Vec::new();
+    for dataset in datasets.iter() {
+        transforms.push(dataset.geo_transform().compat()
+            .map_err(|e| StitchError::Gdal(e.to_string()))?);
+    }
+
+    // use the grid with the smallest pixels as the template
+    let index = match (0..datasets.len()).min_by(|a, b| transforms[*a][1]
+            .abs().partial_cmp(&transforms[*b][1].abs()).unwrap()) {
+        Some(index) => index,
+        None => return Err(StitchError::NoImagery(
+            "no datasets to select bands from".to_string())),
+    };
+
+    let template = &datasets[index];
+    let (width, height) = template.size();
+    let extent = |transform: &[f64; 6], (width, height): (usize, usize)|
+        [transform[0], transform[3], transform[0] + transform[1]
+            * width as f64, transform[3] + transform[5] * height as f64];
+
+    // validate extents agree to within a pixel
+    let template_extent = extent(&transforms[index], (width, height));
+    for (dataset, transform) in datasets.iter().zip(transforms.iter()) {
+        let dataset_extent = extent(transform, dataset.size());
+        if dataset_extent.iter().zip(template_extent.iter())
+                .any(|(a, b)| (a - b).abs() > transform[1].abs()) {
+            return Err(StitchError::Gdal(
+                "band dataset extents differ".to_string()));
+        }
+    }
+
+    // find source bands and the type holding all of their values
+    let mut c_bands = Vec::new();
+    let mut band_type = gdal_sys::GDALDataType::GDT_Byte;
+    for (i, band) in bands.iter() {
+        let c_band = match datasets.get(*i) {
+            Some(dataset) => unsafe {
+                gdal_sys::GDALGetRasterBand(dataset.c_dataset(), *band)
+            },
+            None => std::ptr::null_mut(),
+        };
+
+        if c_band.is_null() {
+            return Err(StitchError::Gdal(format!(
+                "dataset {} has no band {}", i, band)));
+        }
+
+        band_type = unsafe { gdal_sys::GDALDataTypeUnion(band_type,
+            gdal_sys::GDALGetRasterDataType(c_band)) };
+        c_bands.push((c_band, datasets[*i].size()));
+    }
+
+    // create in-memory dataset
+    let driver = Driver::get("MEM").compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    let c_filename = CString::new("").unwrap();
+    let c_dataset = unsafe {
+        gdal_sys::GDALCreate(driver.c_driver(), c_filename.as_ptr(),
+            width as i32, height as i32, bands.len() as i32,
+            band_type, std::ptr::null_mut())
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(last_error()));
+    }
+
+    let dataset = unsafe { Dataset::from_c_dataset(c_dataset) };
+    dataset.set_geo_transform(&transforms[index]).compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+    dataset.set_projection(&template.projection()).compat()
+        .map_err(|e| StitchError::Gdal(e.to_string()))?;
+
+    // copy bands, where gdal resamples coarser bands onto the template
+    let mut buf = vec![0f64; width * height];
+    for (i, (c_src, (src_width, src_height))) in c_bands.iter().enumerate() {
+        let result = unsafe {
+            let c_dst = gdal_sys::GDALGetRasterBand(c_dataset, i as i32 + 1);
+
+            let mut has_no_data = 0;
+            let no_data_value = gdal_sys::GDALGetRasterNoDataValue(
+                *c_src, &mut has_no_data);
+            if has_no_data != 0 {
+                gdal_sys::GDALSetRasterNoDataValue(c_dst, no_data_value);
+            }
+
+            let read = gdal_sys::GDALRasterIO(*c_src,
+                gdal_sys::GDALRWFlag::GF_Read, 0, 0,
+                *src_width as i32, *src_height as i32,
+                buf.as_mut_ptr() as *mut c_void,
+                width as i32, height as i32,
+                gdal_sys::GDALDataType::GDT_Float64, 0, 0);
+            if read != gdal_sys::CPLErr::CE_None {
+                read
+            } else {
+                gdal_sys::GDALRasterIO(c_dst,
+                    gdal_sys::GDALRWFlag::GF_Write, 0, 0,
+                    width as i32, height as i32,
+                    buf.as_mut_ptr() as *mut c_void,
+                    width as i32, height as i32,
+                    gdal_sys::GDALDataType::GDT_Float64, 0, 0)
+            }
+        };
+
+        if result != gdal_sys::CPLErr::CE_None {
+            return Err(StitchError::Gdal(last_error()));
+        }
+    }
+
+    Ok(dataset)
+}
+
+/// reproject and resample dataset into an in-memory dataset
+pub fn warp(dataset: &Dataset, warp: &Warp) -> Result<Dataset, StitchError> {
+    // build gdalwarp arguments
+    let mut args = vec!["-of".to_string(), "MEM".to_string(),
+        "-r".to_string(), warp.resampling.as_str().to_string()];
+    if let Some(target_srs) = &warp.target_srs {
+        args.push("-t_srs".to_string());
+        args.push(target_srs.clone());
+    }
+
+    match (warp.resolution, warp.size) {
+        (Some(_), Some(_)) => return Err(StitchError::Config(
+            "resolution and size are mutually exclusive".to_string())),
+        (Some((x, y)), None) => {
+            args.push("-tr".to_string());
+            args.push(x.to_string());
+            args.push(y.to_string());
+            args.push("-tap".to_string());
+        },
+        (None, Some((width, height))) => {
+            args.push("-ts".to_string());
+            args.push(width.to_string());
+            args.push(height.to_string());
+        },
+        (None, None) => {},
+    }
+
+    let mut c_args_str = Vec::new();
+    for arg in args.iter() {
+        let c_arg = CString::new(arg.as_str()).map_err(|e|
+            StitchError::Config(format!("invalid warp option: {}", e)))?;
+        c_args_str.push(c_arg);
+    }
+
+    let mut c_args: Vec<*mut std::os::raw::c_char> = c_args_str
+        .iter().map(|x| x.as_ptr() as *mut _).collect();
+    c_args.push(std::ptr::null_mut());
+
+    // warp dataset
+    let c_filename = CString::new("").unwrap();
+    let c_dataset = unsafe {
+        let c_options = gdal_sys::GDALWarpAppOptionsNew(
+            c_args.as_mut_ptr(), std::ptr::null_mut());
+        if c_options.is_null() {
+            return Err(StitchError::Config(format!(
+                "invalid warp options: {}", last_error())));
+        }
+
+        let mut c_src = dataset.c_dataset();
+        let mut usage_error = 0;
+        let c_dataset = gdal_sys::GDALWarp(c_filename.as_ptr(),
+            std::ptr::null_mut(), 1, &mut c_src, c_options,
+            &mut usage_error);
+        gdal_sys::GDALWarpAppOptionsFree(c_options);
+        c_dataset
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(format!(
+            "failed to warp dataset: {}", last_error())));
+    }
+
+    Ok(unsafe { Dataset::from_c_dataset(c_dataset) })
+}
+
+/// rescale each band from its value range onto an 8-bit in-memory dataset,
+/// leaving byte datasets untouched
+pub fn scale_to_byte(dataset: &Dataset) -> Result<Dataset, StitchError> {
+    let c_args_str: Vec<CString> = ["-of", "MEM", "-ot", "Byte", "-scale"]
+        .iter().map(|x| CString::new(*x).unwrap()).collect();
+    let mut c_args: Vec<*mut std::os::raw::c_char> = c_args_str
+        .iter().map(|x| x.as_ptr() as *mut _).collect();
+    c_args.push(std::ptr::null_mut());
+
+    // translate dataset
+    let c_filename = CString::new("").unwrap();
+    let c_dataset = unsafe {
+        let c_options = gdal_sys::GDALTranslateOptionsNew(
+            c_args.as_mut_ptr(), std::ptr::null_mut());
+        if c_options.is_null() {
+            return Err(StitchError::Internal(format!(
+                "invalid translate options: {}", last_error())));
+        }
+
+        let mut usage_error = 0;
+        let c_dataset = gdal_sys::GDALTranslate(c_filename.as_ptr(),
+            dataset.c_dataset(), c_options, &mut usage_error);
+        gdal_sys::GDALTranslateOptionsFree(c_options);
+        c_dataset
+    };
+
+    if c_dataset.is_null() {
+        return Err(StitchError::Gdal(format!(
+            "failed to scale dataset: {}", last_error())));
+    }
+
+    Ok(unsafe { Dataset::from_c_dataset(c_dataset) })
+}
+
+/// check whether every band of the dataset holds 8-bit values
+pub fn is_byte(dataset: &Dataset) -> bool {
+    (1..=dataset.count() as i32).all(|i| unsafe {
+        let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), i);
+        gdal_sys::GDALGetRasterDataType(c_band)
+            == gdal_sys::GDALDataType::GDT_Byte
+    })
+}
+
+pub fn last_error() -> String {
+    let err_msg = unsafe {
+        let c_ptr = gdal_sys::CPLGetLastErrorMsg();
+        let c_str = CStr::from_ptr(c_ptr);
+        c_str.to_string_lossy().into_owned()
+    };
+
+    unsafe { gdal_sys::CPLErrorReset() };
+    err_msg
+}
+
+/// owned ogr spatial reference using longitude / latitude axis order
+pub(crate) struct SpatialRef(pub(crate) gdal_sys::OGRSpatialReferenceH);
+
+impl SpatialRef {
+    pub(crate) fn new(definition: &str) -> Result<SpatialRef, StitchError> {
+        let c_definition = CString::new(definition).map_err(|e|
+            StitchError::Gdal(format!("invalid srs: {}", e)))?;
+
+        let srs = SpatialRef(unsafe {
+            gdal_sys::OSRNewSpatialReference(std::ptr::null()) });
+        let result = unsafe {
+            gdal_sys::OSRSetFromUserInput(srs.0, c_definition.as_ptr()) };
+        if result != gdal_sys::OGRErr::OGRERR_NONE {
+            return Err(StitchError::Gdal(format!(
+                "failed to parse srs: {}", last_error())));
+        }
+
+        unsafe {
+            gdal_sys::OSRSetAxisMappingStrategy(srs.0, gdal_sys
+                ::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+        }
+
+        Ok(srs)
+    }
+}
+
+impl Drop for SpatialRef {
+    fn drop(&mut self) {
+        unsafe { gdal_sys::OSRDestroySpatialReference(self.0) };
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn wkt(srs: &str) -> String {
+        let srs = SpatialRef::new(srs).unwrap();
+        let mut c_wkt = std::ptr::null_mut();
+        unsafe {
+            gdal_sys::OSRExportToWkt(srs.0, &mut c_wkt);
+            let wkt = CStr::from_ptr(c_wkt).to_string_lossy().into_owned();
+            gdal_sys::VSIFree(c_wkt as *mut c_void);
+            wkt
+        }
+    }
+
+    /// in-memory byte dataset on a grid in the given srs
+    pub(crate) fn dataset(srs: &str, transform: [f64; 6], width: i32,
+            height: i32, band_count: i32) -> Dataset {
+        typed_dataset(srs, transform, width, height,
+            band_count, gdal_sys::GDALDataType::GDT_Byte)
+    }
+
+    pub(crate) fn typed_dataset(srs: &str, transform: [f64; 6],
+            width: i32, height: i32, band_count: i32,
+            data_type: gdal_sys::GDALDataType::Type) -> Dataset {
+        let driver = Driver::get("MEM").unwrap();
+        let c_filename = CString::new("").unwrap();
+        let dataset = unsafe {
+            Dataset::from_c_dataset(gdal_sys::GDALCreate(
+                driver.c_driver(), c_filename.as_ptr(), width, height,
+                band_count, data_type, std::ptr::null_mut()))
+        };
+
+        dataset.set_projection(&wkt(srs)).unwrap();
+        dataset.set_geo_transform(&transform).unwrap();
+        dataset
+    }
+
+    #[test]
+    fn grid_extent_snaps_outward() {
+        let transform = [500000.0, 10.0, 0.0, 4500000.0, 0.0, -10.0];
+        let (geo_transform, width, height) = grid_extent(&transform,
+            &[500015.0, 500095.0], &[4499905.0, 4499985.0]);
+
+        assert_eq!(geo_transform,
+            [500010.0, 10.0, 0.0, 4499990.0, 0.0, -10.0]);
+        assert_eq!((width, height), (9, 9));
+    }
+
+    #[test]
+    fn empty_dataset_projects_window() {
+        // 10m utm zone 13n grid offset 5m from the central meridian
+        let transform = [400005.0, 10.0, 0.0, 4600000.0, 0.0, -10.0];
+        let template = dataset("EPSG:32613", transform, 16, 16, 3);
+
+        let dataset = empty_dataset(&template,
+            -105.1, -105.0, 40.4, 40.5).unwrap();
+
+        assert_eq!(dataset.geo_transform().unwrap(),
+            [491505.0, 10.0, 0.0, 4483260.0, 0.0, -10.0]);
+        assert_eq!(dataset.size(), (850, 1111));
+        assert_eq!(dataset.count(), 3);
+        assert_eq!(dataset.projection(), template.projection());
+    }
+
+    #[test]
+    fn scale_to_byte_converts_bands() {
+        let transform = [500000.0, 10.0, 0.0, 4500000.0, 0.0, -10.0];
+        let dataset = typed_dataset("EPSG:32613", transform, 4, 4, 3,
+            gdal_sys::GDALDataType::GDT_UInt16);
+        assert!(!is_byte(&dataset));
+
+        let scaled = scale_to_byte(&dataset).unwrap();
+        assert!(is_byte(&scaled));
+        assert_eq!(scaled.count(), 3);
+        assert_eq!(scaled.size(), (4, 4));
+        assert_eq!(scaled.geo_transform().unwrap(), transform);
+    }
+
+    fn fill(dataset: &Dataset, band: i32, value: f64) {
+        unsafe {
+            let c_band = gdal_sys::GDALGetRasterBand(
+                dataset.c_dataset(), band);
+            gdal_sys::GDALFillRaster(c_band, value, 0.0);
+        }
+    }
+
+    fn pixel(dataset: &Dataset, band: i32, x: i32, y: i32) -> f64 {
+        let mut value = 0f64;
+        let result = unsafe {
+            gdal_sys::GDALRasterIO(
+                gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band),
+                gdal_sys::GDALRWFlag::GF_Read, x, y, 1, 1,
+                &mut value as *mut f64 as *mut c_void, 1, 1,
+                gdal_sys::GDALDataType::GDT_Float64, 0, 0)
+        };
+
+        assert_eq!(result, gdal_sys::CPLErr::CE_None);
+        value
+    }
+
+    #[test]
+    fn select_bands_resamples_onto_finest_grid() {
+        // 20m and 10m grids over the same 40m square
+        let coarse = typed_dataset("EPSG:32613",
+            [500000.0, 20.0, 0.0, 4500000.0, 0.0, -20.0], 2, 2, 1,
+            gdal_sys::GDALDataType::GDT_UInt16);
+        let fine = dataset("EPSG:32613",
+            [500000.0, 10.0, 0.0, 4500000.0, 0.0, -10.0], 4, 4, 2);
+        fill(&coarse, 1, 1000.0);
+        fill(&fine, 1, 1.0);
+        fill(&fine, 2, 2.0);
+
+        let datasets = [coarse, fine];
+        let dataset = select_bands(&datasets, &[(0, 1), (1, 2)]).unwrap();
+        assert_eq!(dataset.size(), (4, 4));
+        assert_eq!(dataset.count(), 2);
+        assert_eq!(dataset.geo_transform().unwrap(),
+            [500000.0, 10.0, 0.0, 4500000.0, 0.0, -10.0]);
+        assert_eq!(pixel(&dataset, 1, 3, 3), 1000.0);
+        assert_eq!(pixel(&dataset, 2, 0, 0), 2.0);
+
+        assert!(select_bands(&datasets, &[(1, 3)]).is_err());
+    }
+
+    #[test]
+    fn select_bands_rejects_mismatched_extents() {
+        let a = dataset("EPSG:32613",
+            [500000.0, 10.0, 0.0, 4500000.0, 0.0, -10.0], 4, 4, 1);
+        let b = dataset("EPSG:32613",
+            [500100.0, 10.0, 0.0, 4500000.0, 0.0, -10.0], 4, 4, 1);
+        assert!(select_bands(&[a, b], &[(0, 1), (1, 1)]).is_err());
+    }
+}
@@ -1,87 +1,108 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use gdal::Dataset;
 use protobuf::{Image, Node};
+use yogi::{BandLayout, ConnectionConfig, StipClient, StitchdClient};
+use yogi::protocol::{ImputeRequest, StipReadRequest, Version};
 
-use std::error::Error;
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use crate::{Coverage, StitchError};
+use crate::raster;
 
 pub enum Tile {
-    Stip(Node, Image),
-    Stitch(Node, Vec<Image>, Image),
+    /// image bands read from stip, restricted to any listed sub-geocodes
+    Stip(Node, Image, BandLayout, Vec<String>),
+    /// sentinel-2 and modis images imputed on the first reachable of
+    /// the listed stitchd endpoints
+    Stitch(Node, Vec<String>, Vec<Image>, Image),
 }
 
 impl Tile {
-    pub fn download(&self) -> Result<Dataset, Box<dyn Error>> {
+    /// uniquely identifies the datasets produced by this tile
+    pub fn cache_key(&self) -> Result<String, StitchError> {
         match self {
-            Tile::Stip(node, image) => {
-                // connect to stip transfer service
-                let mut stream = TcpStream::connect(&node.xfer_addr)?;
-
-                // send readop
-                stream.write_u8(0)?;
-
-                // send path
-                write_string(&image.files[3].path, &mut stream)?;
-
-                // send subgeocode indicator
-                stream.write_u8(0)?;
-
-                // check for failure
-                if stream.read_u8()? != 0 {
-                    let error_message = read_string(&mut stream)?;
-                    return Err(error_message.into())
-                }
-                
-                // read dataset
-                let dataset = st_image::serialize::read(&mut stream)?;
-                return Ok(dataset);
+            Tile::Stip(node, image, layout, subgeocodes) => {
+                let bands: Vec<String> = layout.bands.iter()
+                    .map(|(i, band)| format!("{}:{}", i, band)).collect();
+                Ok(format!("stip\t{}\t{}\t{}\t{}\t{}\t{}", node.xfer_addr,
+                    layout.paths.join(","), bands.join(","), image.geocode,
+                    image.timestamp, subgeocodes.join(",")))
             },
-            Tile::Stitch(node, sentinel2_images, modis_image) => {
-                // connect to stitchd service
-                let addr_fields: Vec<&str> =
-                    node.xfer_addr.split(":").collect();
-                let addr = format!("{}:12289", addr_fields[0]);
+            Tile::Stitch(node, _, sentinel2_images, modis_image) => {
+                let request = ImputeRequest::new(sentinel2_images,
+                    modis_image)?;
+                Ok(format!("stitch\t{}\t{}\t{}\t{}\t{}", node.xfer_addr,
+                    request.sentinel2_paths.join(","), request.modis_path,
+                    request.geocode, request.timestamp))
+            },
+        }
+    }
 
-                let mut stream = TcpStream::connect(&addr)?;
+    /// origin of the tile imagery
+    pub fn coverage(&self) -> Coverage {
+        match self {
+            Tile::Stip(_, _, _, _) => Coverage::Stip,
+            Tile::Stitch(_, _, _, _) => Coverage::Imputed,
+        }
+    }
 
-                // write geohash and timestamp
-                write_string(&modis_image.geocode, &mut stream)?;
-                stream.write_i64::<BigEndian>(modis_image.timestamp)?;
+    /// download the tile, using version only for stitchd requests
+    pub async fn download(&self, version: Version,
+            config: &ConnectionConfig) -> Result<Vec<Dataset>, StitchError> {
+        match self {
+            Tile::Stip(node, _, layout, subgeocodes) => {
+                let client = StipClient::new(node.xfer_addr.clone(),
+                    config.clone());
+                let regions = match subgeocodes.is_empty() {
+                    true => vec![None],
+                    false => subgeocodes.iter().cloned().map(Some).collect(),
+                };
 
-                // write paths
-                stream.write_u8(sentinel2_images.len() as u8)?;
-                for image in sentinel2_images.iter() {
-                    write_string(&image.files[3].path, &mut stream)?;
-                }
+                // read each sub-geocode region concurrently
+                let downloads = regions.into_iter()
+                    .map(|x| read_bands(&client, layout, x));
+                futures::future::try_join_all(downloads).await
+            },
+            Tile::Stitch(_, endpoints, sentinel2_images, modis_image) => {
+                let batch = [(sentinel2_images.clone(), modis_image.clone())];
 
-                write_string(&modis_image.files[1].path, &mut stream)?;
+                // fail over to the next endpoint on network failures
+                let mut result = Err(StitchError::Config(
+                    "no stitchd endpoints configured".to_string()));
+                for (i, addr) in endpoints.iter().enumerate() {
+                    if i != 0 {
+                        config.stats.record_failover();
+                    }
 
-                // check for failure
-                if stream.read_u8()? != 0 {
-                    let error_message = read_string(&mut stream)?;
-                    return Err(error_message.into())
+                    let client = StitchdClient::new(addr.clone(),
+                        version, config.clone());
+                    result = client.impute(&batch).await.map_err(|e| e.into());
+                    match &result {
+                        Err(e) if e.is_transient() => continue,
+                        _ => break,
+                    }
                 }
 
-                // read dataset
-                let dataset = st_image::serialize::read(&mut stream)?;
-                return Ok(dataset);
+                result
             },
         }
     }
 }
 
-pub fn read_string<T: Read>(reader: &mut T)
-        -> Result<String, Box<dyn Error>> {
-    let len = reader.read_u8()?;
-    let mut buf = vec![0u8; len as usize];
-    reader.read_exact(&mut buf)?;
-    Ok(String::from_utf8(buf)?)
-}
+/// read the files of a band layout over a region, extracting and
+/// combining the requested bands
+async fn read_bands(client: &StipClient, layout: &BandLayout,
+        subgeocode: Option<String>) -> Result<Dataset, StitchError> {
+    let downloads = layout.paths.iter().map(|path| {
+        let request = StipReadRequest {
+            subgeocode: subgeocode.clone(),
+            ..StipReadRequest::new(path.clone())
+        };
+
+        async move { client.read(&request).await }
+    });
+
+    let mut datasets = futures::future::try_join_all(downloads).await?;
+    if layout.is_whole_file(datasets[0].count() as usize) {
+        return Ok(datasets.remove(0));
+    }
 
-pub fn write_string<T: Write>(value: &str, writer: &mut T)
-        -> Result<(), Box<dyn Error>> {
-    writer.write_u8(value.len() as u8)?;
-    writer.write(value.as_bytes())?;
-    Ok(())
+    raster::select_bands(&datasets, &layout.bands)
 }
@@ -3,6 +3,7 @@
 import argparse
 import multiprocessing as mp
 import numpy as np
+import os
 from sklearn.preprocessing import LabelEncoder
 import socket
 import sys
@@ -50,7 +51,28 @@ def worker(geohashes, model_path, server_sock, weight_path):
 
             # read batch metadata
             sentinel2_batch, modis_batch, geohash_batch, \
-                timestamp_batch = serialize.read_batch(sock)
+                timestamp_batch, version = serialize.read_batch(sock)
+
+            # ignore clients disconnecting without a request
+            if version is None:
+                sock.close()
+                continue
+
+            # answer capability queries
+            if sentinel2_batch is None:
+                serialize.write_capabilities(args.geohash,
+                    os.path.basename(args.weights), args.max_batch_size,
+                    sock, version)
+                sock.close()
+                continue
+
+            # answer oversize batches with an error rather than dropping them
+            if len(sentinel2_batch) > args.max_batch_size:
+                serialize.write_error('batch size '
+                    + str(len(sentinel2_batch)) + ' exceeds '
+                    + str(args.max_batch_size), sock, version)
+                sock.close()
+                continue
 
             # compute input tensor
             tensor = impute.compile_tensor(sentinel2_batch,
@@ -61,7 +83,7 @@ def worker(geohashes, model_path, server_sock, weight_path):
 
             # write imputed images
             serialize.write_images(imputed_images,
-                sentinel2_batch[0][0], sock)
+                sentinel2_batch[0][0], sock, version)
 
             # close client connection
             sock.close()
@@ -71,6 +93,8 @@ def worker(geohashes, model_path, server_sock, weight_path):
 if __name__ == '__main__':
     # parse arguments
     parser = argparse.ArgumentParser(description='impute stip images')
+    parser.add_argument('-b', '--max-batch-size', type=int,
+        help='largest batch accepted per request', default=254)
     parser.add_argument('-i', '--ip-address', type=str,
         help='server ip address', default='0.0.0.0')
     parser.add_argument('-g', '--geohash', action='append',
@@ -85,6 +109,8 @@ if __name__ == '__main__':
         help='model weights location', required=True)
 
     args = parser.parse_args()
+    if not 0 < args.max_batch_size < 255:
+        parser.error('max batch size must be between 1 and 254')
 
     # open server socket
     server_sock = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
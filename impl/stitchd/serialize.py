@@ -5,7 +5,45 @@ import gdal
 import socket
 import struct
 
+# prefix identifying a versioned (v1) connection
+MAGIC = b'\xffSTX'
+VERSION = 1
+
+# v1 message types
+IMPUTE_BATCH = 1
+DATASET = 2
+ERROR = 3
+DESCRIBE = 5
+CAPABILITIES = 6
+
+# gdal data types of supported sentinel-2 bands
+DATA_TYPES = [gdal.GDT_Byte]
+
 def read_batch(sock):
+    # a client closing without a request (ex. a connection probe) is
+    # reported as a None version
+    peek = sock.recv(1, socket.MSG_PEEK)
+    if len(peek) == 0:
+        return None, None, None, None, None
+
+    # detect protocol version, v0 requests never begin with 0xff
+    version = 0
+    if peek[0] == MAGIC[0]:
+        magic = sock.recv(len(MAGIC), socket.MSG_WAITALL)
+        version = sock.recv(1, socket.MSG_WAITALL)[0]
+        if magic != MAGIC or version != VERSION:
+            raise Exception('unsupported protocol version ' + str(version))
+
+        # read frame header
+        message_type = sock.recv(1, socket.MSG_WAITALL)[0]
+        sock.recv(4, socket.MSG_WAITALL)
+
+        # describe requests have an empty payload
+        if message_type == DESCRIBE:
+            return None, None, None, None, version
+        elif message_type != IMPUTE_BATCH:
+            raise Exception('unexpected message type ' + str(message_type))
+
     # read batch size
     batch_size = sock.recv(1, socket.MSG_WAITALL)[0]
 
@@ -15,9 +53,8 @@ def read_batch(sock):
     timestamp_batch = []
     for i in range(0, batch_size):
         # read geohash and timestamp
-        geohash = read_string(sock)
-        #geohash_batch.append(geohash)
-        geohash_batch.append('9q6qp') # TODO - necessary for testing
+        geohash = read_string(sock, version)
+        geohash_batch.append(geohash)
 
         timestamp_buf = sock.recv(8, socket.MSG_WAITALL)
         timestamp = struct.unpack('>q', timestamp_buf)[0]
@@ -27,19 +64,25 @@ def read_batch(sock):
         sentinel2_count = sock.recv(1, socket.MSG_WAITALL)[0]
         sentinel2_paths = []
         for i in range(0, sentinel2_count):
-            path = read_string(sock)
+            path = read_string(sock, version)
             sentinel2_paths.append(path)
         sentinel2_batch.append(sentinel2_paths);
 
-        modis_path = read_string(sock)
+        modis_path = read_string(sock, version)
         modis_batch.append(modis_path)
 
-    return sentinel2_batch, modis_batch, geohash_batch, timestamp_batch
+    return sentinel2_batch, modis_batch, \
+        geohash_batch, timestamp_batch, version
 
-def read_string(sock):
-    length_buf = sock.recv(1, socket.MSG_WAITALL)
-    length = struct.unpack('>B', length_buf)[0]
-    buf = sock.recv(length)
+def read_string(sock, version=0):
+    if version == 0:
+        length_buf = sock.recv(1, socket.MSG_WAITALL)
+        length = struct.unpack('>B', length_buf)[0]
+    else:
+        length_buf = sock.recv(4, socket.MSG_WAITALL)
+        length = struct.unpack('>I', length_buf)[0]
+
+    buf = sock.recv(length, socket.MSG_WAITALL)
     value = buf.decode('utf-8')
     return value
 
@@ -48,15 +91,69 @@ def write_string(string, sock):
     sock.sendall(struct.pack('>I', len(buf)))
     sock.sendall(buf)
 
-def write_images(imputed_images, sentinel2_path, sock):
+def write_capabilities(geohashes, model_version,
+        max_batch_size, sock, version=VERSION):
+    frame = FrameWriter()
+
+    # write model version and max batch size
+    write_string(model_version, frame)
+    frame.sendall(struct.pack('>B', max_batch_size))
+
+    # write geohashes
+    frame.sendall(struct.pack('>I', len(geohashes)))
+    for geohash in geohashes:
+        write_string(geohash, frame)
+
+    # write supported data types
+    frame.sendall(struct.pack('>B', len(DATA_TYPES)))
+    for data_type in DATA_TYPES:
+        frame.sendall(struct.pack('>I', data_type))
+
+    sock.sendall(MAGIC + bytes([version]))
+    frame.flush(CAPABILITIES, sock)
+
+def write_error(message, sock, version=0):
+    buf = str.encode(message)
+
+    # write failure status (v0) or an error frame (v1)
+    if version == 0:
+        buf = buf[:255]
+        sock.sendall(struct.pack('>BB', 1, len(buf)))
+        sock.sendall(buf)
+    else:
+        sock.sendall(MAGIC + bytes([version]))
+        sock.sendall(struct.pack('>BI', ERROR, len(buf)))
+        sock.sendall(buf)
+
+class FrameWriter:
+    # buffers a single v1 frame so its length can be written first
+    def __init__(self):
+        self.buf = bytearray()
+
+    def sendall(self, data):
+        self.buf.extend(data)
+
+    def flush(self, message_type, sock):
+        sock.sendall(struct.pack('>BI', message_type, len(self.buf)))
+        sock.sendall(self.buf)
+
+def write_images(imputed_images, sentinel2_path, sock, version=0):
     # open datset
     dataset = gdal.Open(sentinel2_path)
 
-    # write success
-    sock.sendall(struct.pack('B', 0))
+    # write success (v0) or handshake (v1)
+    if version == 0:
+        sock.sendall(struct.pack('B', 0))
+    else:
+        sock.sendall(MAGIC + bytes([version]))
 
     #for i in range(0, batch_size):
+    client_sock = sock
     for imputed_image in imputed_images:
+        # v1 images are written as length-prefixed frames
+        if version != 0:
+            sock = FrameWriter()
+
         # write image dimensions
         sock.sendall(struct.pack('>I', dataset.RasterXSize))
         sock.sendall(struct.pack('>I', dataset.RasterYSize))
@@ -125,3 +222,8 @@ def write_images(imputed_images, sentinel2_path, sock):
             #else:
             #    # TODO - throw error
             #    print('unsupported data type')
+
+        # write buffered v1 frame
+        if version != 0:
+            sock.flush(DATASET, client_sock)
+            sock = client_sock
@@ -2,6 +2,7 @@
 
 import argparse
 import numpy as np
+import os
 from sklearn.preprocessing import LabelEncoder
 import socket
 import sys
@@ -17,6 +18,8 @@ tf.compat.v1.disable_eager_execution()
 if __name__ == '__main__':
     # parse arguments
     parser = argparse.ArgumentParser(description='impute stip images')
+    parser.add_argument('-b', '--max-batch-size', type=int,
+        help='largest batch accepted per request', default=254)
     parser.add_argument('-i', '--ip-address', type=str,
         help='server ip address', default='0.0.0.0')
     parser.add_argument('-g', '--geohash', action='append',
@@ -29,6 +32,8 @@ if __name__ == '__main__':
         help='model weights location', required=True)
 
     args = parser.parse_args()
+    if not 0 < args.max_batch_size < 255:
+        parser.error('max batch size must be between 1 and 254')
 
     # initialize encoder
     le = LabelEncoder()
@@ -65,15 +70,36 @@ if __name__ == '__main__':
         sys.exit()
 
     # listen for client connections
-    try:
-        server_sock.listen()
-        while 1:
+    server_sock.listen()
+    while 1:
+        try:
             # accept connection
             sock, address = server_sock.accept()
 
             # read batch metadata
             sentinel2_batch, modis_batch, geohash_batch, \
-                timestamp_batch = serialize.read_batch(sock)
+                timestamp_batch, version = serialize.read_batch(sock)
+
+            # ignore clients disconnecting without a request
+            if version is None:
+                sock.close()
+                continue
+
+            # answer capability queries
+            if sentinel2_batch is None:
+                serialize.write_capabilities(args.geohash,
+                    os.path.basename(args.weights), args.max_batch_size,
+                    sock, version)
+                sock.close()
+                continue
+
+            # answer oversize batches with an error rather than dropping them
+            if len(sentinel2_batch) > args.max_batch_size:
+                serialize.write_error('batch size '
+                    + str(len(sentinel2_batch)) + ' exceeds '
+                    + str(args.max_batch_size), sock, version)
+                sock.close()
+                continue
 
             # compute input tensor
             tensor = impute.compile_tensor(sentinel2_batch,
@@ -84,15 +110,13 @@ if __name__ == '__main__':
 
             # write imputed images
             serialize.write_images(imputed_images,
-                sentinel2_batch[0][0], sock)
+                sentinel2_batch[0][0], sock, version)
 
             # close client connection
             sock.close()
-    except KeyboardInterrupt:
-        if server_sock:
-            server_sock.close()
-    except:
-        traceback.print_exc()
-
-    if server_sock:
-        server_sock.close()
+        except KeyboardInterrupt:
+            break
+        except:
+            traceback.print_exc()
+
+    server_sock.close()
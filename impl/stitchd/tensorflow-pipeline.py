@@ -3,6 +3,7 @@
 import argparse
 import multiprocessing as mp
 import numpy as np
+import os
 from sklearn.preprocessing import LabelEncoder
 import socket
 import sys
@@ -29,11 +30,29 @@ def io_worker(geohashes, pipe, server_sock):
             # read batch metadata
             #read_start = time.time()
             sentinel2_batch, modis_batch, geohash_batch, \
-                timestamp_batch = serialize.read_batch(sock)
+                timestamp_batch, version = serialize.read_batch(sock)
             #read_duration = time.time() - read_start
 
-            if len(sentinel2_batch) > 1:
-                raise Exception('batch_size > 1 not supported')
+            # ignore clients disconnecting without a request
+            if version is None:
+                sock.close()
+                continue
+
+            # answer capability queries
+            if sentinel2_batch is None:
+                serialize.write_capabilities(args.geohash,
+                    os.path.basename(args.weights), args.max_batch_size,
+                    sock, version)
+                sock.close()
+                continue
+
+            # answer oversize batches with an error rather than dropping them
+            if len(sentinel2_batch) > args.max_batch_size:
+                serialize.write_error('batch size '
+                    + str(len(sentinel2_batch)) + ' exceeds '
+                    + str(args.max_batch_size), sock, version)
+                sock.close()
+                continue
 
             # compute input tensor
             #compile_start = time.time()
@@ -50,7 +69,7 @@ def io_worker(geohashes, pipe, server_sock):
             # write imputed images
             #write_start = time.time()
             serialize.write_images(imputed_images,
-                sentinel2_batch[0][0], sock)
+                sentinel2_batch[0][0], sock, version)
             #write_duration = time.time() - write_start
 
             #print(str(read_duration) + ' ' + str(compile_duration) + ' '
@@ -97,10 +116,10 @@ def impute_worker(model_path, pipes, weights_path):
                 if pipes[index].poll():
                     pipe_tensor = pipes[index].recv()
 
-                    # append data
-                    indices.append(index)
+                    # append data, recording the batch size of each pipe
+                    indices.append((index, len(pipe_tensor[0])))
                     for j in range(len(tensor)):
-                        tensor[j].append(pipe_tensor[j][0])
+                        tensor[j].extend(pipe_tensor[j])
 
                 index = (index + 1) % len(pipes)
                 count += 1
@@ -114,8 +133,11 @@ def impute_worker(model_path, pipes, weights_path):
             imputed_images = impute.impute_batch(model, tensor)
 
             # write imputed images to pipe
-            for i in range(len(imputed_images)):
-                pipes[indices[i]].send([imputed_images[i]])
+            offset = 0
+            for pipe_index, batch_size in indices:
+                pipes[pipe_index].send(
+                    imputed_images[offset:offset + batch_size])
+                offset += batch_size
 
             # clear indices and tensor
             indices.clear()
@@ -127,6 +149,8 @@ def impute_worker(model_path, pipes, weights_path):
 if __name__ == '__main__':
     # parse arguments
     parser = argparse.ArgumentParser(description='impute stip images')
+    parser.add_argument('-b', '--max-batch-size', type=int,
+        help='largest batch accepted per request', default=254)
     parser.add_argument('-i', '--ip-address', type=str,
         help='server ip address', default='0.0.0.0')
     parser.add_argument('-g', '--geohash', action='append',
@@ -141,6 +165,8 @@ if __name__ == '__main__':
         help='model weights location', required=True)
 
     args = parser.parse_args()
+    if not 0 < args.max_batch_size < 255:
+        parser.error('max batch size must be between 1 and 254')
 
     # open server socket
     server_sock = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
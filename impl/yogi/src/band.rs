@@ -0,0 +1,330 @@
+use protobuf::{File, Image};
+
+use crate::YogiError;
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// resolution of the modis surface reflectance grid holding the red,
+/// green, and blue bands used for imputation
+const MODIS_RESOLUTION: u32 = 500;
+
+/// bands requested from sentinel-2 images
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum BandSet {
+    /// 8-bit red, green, blue composite, the only bands imputed
+    #[default]
+    TrueColor,
+    /// every named band, each read at its finest resolution
+    All,
+    /// named bands (ex. 'B8'), in the order requested
+    Bands(Vec<String>),
+}
+
+impl Display for BandSet {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            BandSet::TrueColor => write!(f, "rgb"),
+            BandSet::All => write!(f, "all"),
+            BandSet::Bands(bands) => write!(f, "{}", bands.join(",")),
+        }
+    }
+}
+
+impl FromStr for BandSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BandSet, String> {
+        match s.to_lowercase().as_str() {
+            "rgb" => return Ok(BandSet::TrueColor),
+            "all" => return Ok(BandSet::All),
+            "nir" => return Ok(BandSet::Bands(vec!["B8".to_string()])),
+            _ => {},
+        }
+
+        let bands: Vec<String> = s.split(',')
+            .map(|x| x.trim().to_uppercase())
+            .filter(|x| !x.is_empty()).collect();
+        if bands.is_empty() {
+            return Err(format!("invalid band set '{}'", s));
+        }
+
+        if let Some(band) = bands.iter().find(|x| !is_band_name(x)) {
+            return Err(format!("invalid band '{}'", band));
+        }
+
+        Ok(BandSet::Bands(bands))
+    }
+}
+
+/// image files read for a band set, and where each output band is found
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandLayout {
+    pub paths: Vec<String>,
+    /// (index into paths, 1-based band within that file) of each output
+    /// band, in output order
+    pub bands: Vec<(usize, i32)>,
+}
+
+impl BandLayout {
+    /// whether a single file of band_count bands is read unchanged
+    pub fn is_whole_file(&self, band_count: usize) -> bool {
+        self.paths.len() == 1 && self.bands.len() == band_count
+            && self.bands.iter().enumerate()
+                .all(|(i, x)| *x == (0, i as i32 + 1))
+    }
+}
+
+impl BandSet {
+    /// locate the files, and bands within them, holding these bands, or
+    /// none if the image lacks any of them
+    ///
+    /// gdal describes sentinel-2 subdatasets as, for example,
+    ///  'Bands B2, B3, B4, B8 with 10m resolution, UTM 13N'
+    ///  'True color image, UTM 13N'
+    /// so bands are numbered in the order their description lists them,
+    /// and read from the finest resolution file listing them
+    pub fn layout(&self, image: &Image) -> Option<BandLayout> {
+        if *self == BandSet::TrueColor {
+            let file = image.files.iter()
+                .find(|x| is_true_color(&x.description))?;
+            return Some(BandLayout {
+                paths: vec![file.path.clone()],
+                bands: vec![(0, 1), (0, 2), (0, 3)],
+            });
+        }
+
+        // order files from finest to coarsest resolution
+        let mut files: Vec<&File> = image.files.iter()
+            .filter(|x| !is_true_color(&x.description)).collect();
+        files.sort_by_key(|x| resolution(&x.description)
+            .unwrap_or(u32::MAX));
+
+        let names = match self {
+            BandSet::Bands(bands) => bands.clone(),
+            _ => {
+                let mut names = Vec::new();
+                for file in files.iter() {
+                    for name in band_names(&file.description) {
+                        if !names.contains(&name) {
+                            names.push(name);
+                        }
+                    }
+                }
+
+                names
+            },
+        };
+
+        if names.is_empty() {
+            return None;
+        }
+
+        let mut layout = BandLayout { paths: Vec::new(), bands: Vec::new() };
+        for name in names.iter() {
+            let (file, band) = files.iter().find_map(|file| {
+                band_names(&file.description).iter()
+                    .position(|x| x == name)
+                    .map(|i| (file, i as i32 + 1))
+            })?;
+
+            let index = match layout.paths.iter()
+                    .position(|x| *x == file.path) {
+                Some(index) => index,
+                None => {
+                    layout.paths.push(file.path.clone());
+                    layout.paths.len() - 1
+                },
+            };
+
+            layout.bands.push((index, band));
+        }
+
+        Some(layout)
+    }
+
+    pub fn resolve(&self, image: &Image) -> Result<BandLayout, YogiError> {
+        self.layout(image).ok_or_else(|| YogiError::MissingBands(format!(
+            "{} image {} at {} lacks bands {}",
+            image.platform, image.geocode, image.timestamp, self)))
+    }
+
+    /// path of the single file holding exactly these bands
+    pub fn path(&self, image: &Image) -> Result<String, YogiError> {
+        let layout = self.resolve(image)?;
+        if !layout.is_whole_file(layout.bands.len()) {
+            return Err(YogiError::MissingBands(format!(
+                "{} image {} at {} has no single file of bands {}",
+                image.platform, image.geocode, image.timestamp, self)));
+        }
+
+        Ok(layout.paths[0].clone())
+    }
+}
+
+/// find the modis surface reflectance file used for imputation
+pub fn modis_file(image: &Image) -> Option<&File> {
+    image.files.iter()
+        .find(|x| resolution(&x.description) == Some(MODIS_RESOLUTION))
+}
+
+pub fn modis_path(image: &Image) -> Result<String, YogiError> {
+    match modis_file(image) {
+        Some(file) => Ok(file.path.clone()),
+        None => Err(YogiError::MissingBands(format!(
+            "{} image {} at {} has no {}m surface reflectance file",
+            image.platform, image.geocode, image.timestamp,
+            MODIS_RESOLUTION))),
+    }
+}
+
+/// alphanumeric words of a description
+fn words(description: &str) -> impl Iterator<Item = &str> {
+    description.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|x| !x.is_empty())
+}
+
+/// sentinel-2 band names (ex. 'B8A') listed in a description, in order
+fn band_names(description: &str) -> Vec<String> {
+    words(description).map(|x| x.to_uppercase())
+        .filter(|x| is_band_name(x)).collect()
+}
+
+fn is_band_name(value: &str) -> bool {
+    match value.strip_prefix('B') {
+        Some(number) => {
+            let number = number.strip_suffix('A').unwrap_or(number);
+            !number.is_empty() && number.len() <= 2
+                && number.chars().all(|c| c.is_ascii_digit())
+        },
+        None => false,
+    }
+}
+
+fn is_true_color(description: &str) -> bool {
+    let description = description.to_lowercase();
+    description.contains("true color")
+        || words(&description).any(|x| x == "tci")
+}
+
+/// ground resolution in meters (ex. '10m' or 'MODIS_Grid_500m_2D')
+fn resolution(description: &str) -> Option<u32> {
+    words(description).find_map(|x| x.strip_suffix('m')
+        .and_then(|x| x.parse::<u32>().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::{File, Image};
+
+    use super::{BandLayout, BandSet};
+
+    fn image(platform: &str, descriptions: &[&str]) -> Image {
+        Image {
+            cloud_coverage: None,
+            files: descriptions.iter().enumerate().map(|(i, x)| File {
+                description: x.to_string(),
+                path: format!("/data/{}.tif", i),
+                pixel_coverage: 1.0,
+                subdataset: i as i32,
+            }).collect(),
+            geocode: "9xj3e".to_string(),
+            platform: platform.to_string(),
+            source: "test".to_string(),
+            timestamp: 1534095541,
+        }
+    }
+
+    /// level-1c subdatasets, listed out of resolution order
+    fn sentinel2() -> Image {
+        image("Sentinel-2", &[
+            "True color image, UTM 13N",
+            "Bands B5, B6, B7, B8A, B11, B12 with 20m resolution, UTM 13N",
+            "Bands B1, B9, B10 with 60m resolution, UTM 13N",
+            "Bands B2, B3, B4, B8 with 10m resolution, UTM 13N",
+        ])
+    }
+
+    fn layout(paths: &[usize], bands: &[(usize, i32)]) -> BandLayout {
+        BandLayout {
+            paths: paths.iter().map(|x| format!("/data/{}.tif", x))
+                .collect(),
+            bands: bands.to_vec(),
+        }
+    }
+
+    #[test]
+    fn layout_from_descriptions() {
+        let image = sentinel2();
+        assert_eq!(BandSet::TrueColor.layout(&image).unwrap(),
+            layout(&[0], &[(0, 1), (0, 2), (0, 3)]));
+        assert_eq!(BandSet::TrueColor.path(&image).unwrap(),
+            "/data/0.tif");
+
+        // only the near-infrared band of the 10m file
+        let nir: BandSet = "nir".parse().unwrap();
+        assert_eq!(nir.layout(&image).unwrap(), layout(&[3], &[(0, 4)]));
+        assert!(nir.path(&image).is_err());
+
+        // bands spanning files keep the requested order
+        let bands: BandSet = "b8a, b2, b11".parse().unwrap();
+        assert_eq!(bands.layout(&image).unwrap(),
+            layout(&[1, 3], &[(0, 4), (1, 1), (0, 5)]));
+    }
+
+    #[test]
+    fn layout_all_bands() {
+        let layout = BandSet::All.layout(&sentinel2()).unwrap();
+        assert_eq!(layout.paths,
+            vec!["/data/3.tif", "/data/1.tif", "/data/2.tif"]);
+        assert_eq!(layout.bands.len(), 13);
+        assert_eq!(layout.bands[..4], [(0, 1), (0, 2), (0, 3), (0, 4)]);
+        assert_eq!(layout.bands[12], (2, 3));
+    }
+
+    #[test]
+    fn layout_prefers_finest_resolution() {
+        // level-2a 20m files repeat the 10m bands
+        let image = image("Sentinel-2", &[
+            "Bands B2, B3, B4, B5, B8A with 20m resolution, UTM 13N",
+            "Bands B2, B3, B4, B8 with 10m resolution, UTM 13N",
+        ]);
+
+        let bands: BandSet = "B4,B5".parse().unwrap();
+        assert_eq!(bands.layout(&image).unwrap(),
+            layout(&[1, 0], &[(0, 3), (1, 4)]));
+    }
+
+    #[test]
+    fn layout_missing_bands() {
+        let image = image("Sentinel-2",
+            &["Bands B2, B3, B4, B8 with 10m resolution, UTM 13N"]);
+        assert!(BandSet::TrueColor.layout(&image).is_none());
+        assert!("B2,B5".parse::<BandSet>().unwrap()
+            .layout(&image).is_none());
+        assert!(BandSet::TrueColor.resolve(&image).is_err());
+    }
+
+    #[test]
+    fn modis_surface_reflectance() {
+        let image = image("MODIS", &[
+            "[2400x2400] sur_refl_b01 MODIS_Grid_250m_2D (16-bit integer)",
+            "[2400x2400] sur_refl_b01 MODIS_Grid_500m_2D (16-bit integer)",
+        ]);
+        assert_eq!(super::modis_path(&image).unwrap(), "/data/1.tif");
+
+        let image = self::image("MODIS", &["MODIS_Grid_250m_2D"]);
+        assert!(super::modis_path(&image).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("RGB".parse::<BandSet>().unwrap(), BandSet::TrueColor);
+        assert_eq!("all".parse::<BandSet>().unwrap(), BandSet::All);
+        assert_eq!("b4, B8A".parse::<BandSet>().unwrap(),
+            BandSet::Bands(vec!["B4".to_string(), "B8A".to_string()]));
+        assert!("B2,red".parse::<BandSet>().is_err());
+        assert!("B123".parse::<BandSet>().is_err());
+        assert!(",".parse::<BandSet>().is_err());
+    }
+}
@@ -1,11 +1,10 @@
-use byteorder::{ReadBytesExt, WriteBytesExt};
 use crossbeam_channel::{Receiver, Sender};
 use protobuf::{Filter, Image};
 use structopt::StructOpt;
+use yogi::{BandSet, ClientPool, ConnectionOpt, StipClient, YogiError};
+use yogi::protocol::StipReadRequest;
 
-use std::error::Error;
-use std::io::{Read, Write};
-use std::net::{IpAddr, TcpStream};
+use std::net::IpAddr;
 use std::time::Instant;
 
 #[derive(Clone, Debug, StructOpt)]
@@ -14,6 +13,13 @@ struct Opt {
     #[structopt(short, long, help="stip album", default_value="test")]
     album: String,
 
+    #[structopt(short, long, help="sentinel-2 bands to read ('rgb', \
+        'nir', 'all', or a list like 'B4,B8')", default_value="rgb")]
+    bands: BandSet,
+
+    #[structopt(flatten)]
+    connection: ConnectionOpt,
+
     #[structopt(short, long,
         help="stip node ip address", default_value="127.0.0.1")]
     ip_address: IpAddr,
@@ -40,6 +46,15 @@ fn main() {
     // parse command line options
     let opt = Opt::from_args();
 
+    // initialize grpc client pool
+    let pool = ClientPool::new();
+    let rpc_addr = format!("{}:{}", &opt.ip_address, opt.port);
+    let mut runtime = match tokio::runtime::Builder::new()
+            .basic_scheduler().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => panic!("failed to start runtime: {}", e),
+    };
+
     // get all Sentinel-2 images
     let sentinel2_filter = Filter {
         end_timestamp: opt.timestamp_end,
@@ -52,15 +67,18 @@ fn main() {
         start_timestamp: opt.timestamp_start,
     };
 
-    let sentinel2_images = match yogi::get_images(
-            &opt.album, sentinel2_filter, 
-            &format!("{}:{}", &opt.ip_address, opt.port)) {
+    let sentinel2_images = match runtime.block_on(yogi::get_images(
+            &pool, &opt.album, sentinel2_filter, &rpc_addr)) {
         Ok(images) => images,
         Err(e) => panic!("failed to get sentinel-2: {}", e),
     };
 
     let sentinel2_images: Vec<Image> = sentinel2_images
-        .into_iter().filter(|x| x.files.len() == 4).collect();
+        .into_iter().filter(|x| opt.bands.layout(x).is_some()).collect();
+
+    // initialize transfer connection settings
+    let config = opt.connection.config();
+    let stats = config.stats.clone();
 
     // open channels
     let (tx, rx): (Sender<Image>, Receiver<Image>) = 
@@ -71,10 +89,22 @@ fn main() {
     for _ in 0..opt.thread_count {
         let rx = rx.clone();
         let opt = opt.clone();
+        let config = config.clone();
 
         let join_handle = std::thread::spawn(move || {
+            // initialize stip client
+            let mut runtime = match tokio::runtime::Builder::new()
+                    .basic_scheduler().enable_all().build() {
+                Ok(runtime) => runtime,
+                Err(e) => panic!("failed to start runtime: {}", e),
+            };
+
+            let addr = format!("{}:{}", opt.ip_address, opt.xfer_port);
+            let client = StipClient::new(addr, config);
+
             for image in rx.iter() {
-                if let Err(e) = process(&image, &opt) {
+                if let Err(e) = runtime.block_on(
+                        process(&client, &image, &opt)) {
                     println!("image process failed: {}", e);
                 }
             }
@@ -104,34 +134,19 @@ fn main() {
     }
 
     let duration = instant.elapsed();
-    println!("imputed {} image(s) in {}.{}", count,
-        duration.as_secs(), duration.subsec_nanos());
+    println!("read {} image(s) in {}.{} with {} retries", count,
+        duration.as_secs(), duration.subsec_nanos(), stats.retries());
 }
 
-fn process(image: &Image, opt: &Opt) -> Result<(), Box<dyn Error>> {
-    // connect to stitchd service
-    let addr = format!("{}:{}", opt.ip_address, opt.xfer_port);
-    let mut stream = TcpStream::connect(&addr)?;
-
+async fn process(client: &StipClient, image: &Image, opt: &Opt)
+        -> Result<(), YogiError> {
     let instant = Instant::now();
 
-    // send readop
-    stream.write_u8(0)?;
-
-    // send path
-    write_string(&image.files[3].path, &mut stream)?;
-
-    // send subgeocode indicator
-    stream.write_u8(0)?;
-
-    // check for failure
-    if stream.read_u8()? != 0 {
-        let error_message = read_string(&mut stream)?;
-        return Err(error_message.into())
+    // read each file holding the requested bands
+    for path in opt.bands.resolve(image)?.paths {
+        let request = StipReadRequest::new(path);
+        let _ = client.read(&request).await?;
     }
-    
-    // read dataset
-    let _ = st_image::serialize::read(&mut stream)?;
 
     let duration = instant.elapsed();
     println!("processed image in {}.{}",
@@ -139,18 +154,3 @@ fn process(image: &Image, opt: &Opt) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
-
-fn read_string<T: Read>(reader: &mut T)
-        -> Result<String, Box<dyn Error>> {
-    let len = reader.read_u8()?;
-    let mut buf = vec![0u8; len as usize];
-    reader.read_exact(&mut buf)?;
-    Ok(String::from_utf8(buf)?)
-}
-
-fn write_string<T: Write>(value: &str, writer: &mut T)
-        -> Result<(), Box<dyn Error>> {
-    writer.write_u8(value.len() as u8)?;
-    writer.write(value.as_bytes())?;
-    Ok(())
-}
@@ -1,12 +1,13 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use crossbeam_channel::{Receiver, Sender};
 use protobuf::{Filter, Image};
 use structopt::StructOpt;
+use yogi::{BandSet, ClientPool, ConfigPolicy, ConnectionOpt, DispatchEvent, Dispatcher, Endpoint, EndpointRegistry, SelectionConfig, SelectionPolicy, YogiError};
+use yogi::protocol::Version;
 
-use std::error::Error;
-use std::io::{Read, Write};
-use std::net::{IpAddr, TcpStream};
-use std::time::Instant;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug, StructOpt)]
 #[structopt(name="stitchd")]
@@ -17,6 +18,22 @@ struct Opt {
     #[structopt(short, long, help="size of batches", default_value="1")]
     batch_size: usize,
 
+    #[structopt(flatten)]
+    connection: ConnectionOpt,
+
+    #[structopt(short="c", long,
+        help="maximum sentinel-2 cloud coverage percentage")]
+    max_cloud_coverage: Option<f64>,
+
+    #[structopt(long, help="seconds between health checks of failed \
+        stitchd servers", default_value="10")]
+    health_interval: u64,
+
+    #[structopt(long, help="stitchd hosts file of 'host port \
+        [-g GEOHASH]...' lines (ex. 'etc/hosts.txt'), defaults to port \
+        12289 on the stip node")]
+    hosts_file: Option<PathBuf>,
+
     #[structopt(short, long,
         help="stip node ip address", default_value="127.0.0.1")]
     ip_address: IpAddr,
@@ -25,6 +42,17 @@ struct Opt {
         help="stip node rpc port", default_value="15606")]
     port: u16,
 
+    #[structopt(long, help="geocode precision of the album \
+        partitioning", default_value="5")]
+    precision: usize,
+
+    #[structopt(long, help="tile selection configuration file")]
+    selection_config: Option<PathBuf>,
+
+    #[structopt(long, help="stitchd transfer protocol version, v1 \
+        enables capability checks", default_value="0")]
+    stitchd_protocol_version: Version,
+
     #[structopt(short, long, help="thread count", default_value="4")]
     thread_count: u8,
 
@@ -39,77 +67,168 @@ fn main() {
     // parse command line options
     let opt = Opt::from_args();
 
+    // initialize grpc client pool
+    let pool = ClientPool::new();
+    let rpc_addr = format!("{}:{}", &opt.ip_address, opt.port);
+    let mut runtime = match tokio::runtime::Builder::new()
+            .basic_scheduler().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => panic!("failed to start runtime: {}", e),
+    };
+
+    // initialize image selection, stitchd models are fit on three
+    // sentinel-2 images unless configured otherwise
+    let mut selection_config = match &opt.selection_config {
+        Some(path) => match SelectionConfig::from_file(path) {
+            Ok(config) => config,
+            Err(e) => panic!("failed to load selection config: {}", e),
+        },
+        None => SelectionConfig {
+            sentinel2_count: 3,
+            ..SelectionConfig::default()
+        },
+    };
+
+    if opt.max_cloud_coverage.is_some() {
+        selection_config.sentinel2_max_cloud_coverage =
+            opt.max_cloud_coverage;
+    }
+
     // get all Sentinel-2 images
     let sentinel2_filter = Filter {
         end_timestamp: opt.timestamp_end,
         geocode: None,
-        max_cloud_coverage: None,
-        min_pixel_coverage: Some(1.0),
+        max_cloud_coverage: selection_config.sentinel2_max_cloud_coverage,
+        min_pixel_coverage: selection_config.sentinel2_min_pixel_coverage,
         platform: Some("Sentinel-2".to_string()),
         recurse: false,
         source: None,
         start_timestamp: opt.timestamp_start,
     };
 
-    let sentinel2_images = match yogi::get_images(
-            &opt.album, sentinel2_filter,
-            &format!("{}:{}", &opt.ip_address, opt.port)) {
+    let sentinel2_images = match runtime.block_on(yogi::get_images(
+            &pool, &opt.album, sentinel2_filter, &rpc_addr)) {
         Ok(images) => images,
         Err(e) => panic!("failed to get sentinel-2: {}", e),
     };
 
     let sentinel2_images: Vec<Image> = sentinel2_images
-        .into_iter().filter(|x| x.files.len() == 4).collect();
+        .into_iter().filter(|x| BandSet::TrueColor.layout(x).is_some())
+        .collect();
 
     // get all MODIS images
     let modis_filter = Filter {
         end_timestamp: opt.timestamp_end,
         geocode: None,
         max_cloud_coverage: None,
-        min_pixel_coverage: None,
+        min_pixel_coverage: selection_config.modis_min_pixel_coverage,
         platform: Some("MODIS".to_string()),
         recurse: false,
         source: None,
         start_timestamp: opt.timestamp_start,
     };
 
-    let modis_images = match yogi::get_images(&opt.album, modis_filter,
-            &format!("{}:{}", &opt.ip_address, opt.port)) {
+    let modis_images = match runtime.block_on(yogi::get_images(
+            &pool, &opt.album, modis_filter, &rpc_addr)) {
         Ok(images) => images,
         Err(e) => panic!("failed to get modis: {}", e),
     };
 
     let modis_images: Vec<Image> = modis_images
-        .into_iter().filter(|x| x.files.len() == 2
-            && x.geocode.len() == 5).collect();
+        .into_iter().filter(|x| yogi::modis_file(x).is_some()
+            && x.geocode.len() == opt.precision).collect();
+
+    // initialize transfer connection settings
+    let config = opt.connection.config();
+    let stats = config.stats.clone();
+    let failed_count = Arc::new(AtomicUsize::new(0));
+
+    // initialize stitchd endpoints
+    let mut endpoints = match &opt.hosts_file {
+        Some(path) => match EndpointRegistry::from_hosts_file(path) {
+            Ok(endpoints) => endpoints.endpoints().to_vec(),
+            Err(e) => panic!("failed to load stitchd hosts: {}", e),
+        },
+        None => vec![Endpoint::new(opt.ip_address.to_string(),
+            yogi::DEFAULT_STITCHD_PORT)],
+    };
+
+    // query stitchd capabilities, servers which cannot be described are
+    // assumed to accept any request
+    if opt.stitchd_protocol_version == Version::V1 {
+        for endpoint in endpoints.iter_mut() {
+            match runtime.block_on(endpoint.describe(
+                    opt.stitchd_protocol_version, &config)) {
+                Ok(()) => if let Some(capabilities) = &endpoint.capabilities {
+                    println!("{}: model '{}', max batch size {}, \
+                        {} geohash(es)", endpoint.addr(),
+                        capabilities.model_version,
+                        capabilities.max_batch_size,
+                        capabilities.geohashes.len());
+                },
+                Err(e) => println!("failed to describe {}: {}",
+                    endpoint.addr(), e),
+            }
+        }
+    }
+
+    let dispatcher = Dispatcher::new(endpoints,
+            opt.stitchd_protocol_version, config.clone(),
+            Duration::from_secs(opt.health_interval))
+        .on_event(|event| match event {
+            DispatchEvent::Requeued { addr, error } =>
+                println!("requeueing batch from {}: {}", addr, error),
+            DispatchEvent::Restored { addr } =>
+                println!("stitchd server {} is healthy", addr),
+        });
+    let dispatcher = Arc::new(dispatcher);
+
+    match dispatcher.max_batch_size() {
+        Some(max_batch_size) if opt.batch_size > max_batch_size =>
+            panic!("batch size {} exceeds stitchd maximum {}",
+                opt.batch_size, max_batch_size),
+        _ => {},
+    }
 
     // open channels
-    let (tx, rx): (Sender<(Vec<Image>, Image)>, Receiver<(Vec<Image>, Image)>) = 
-        crossbeam_channel::unbounded();
+    let (tx, rx) = crossbeam_channel::unbounded::<(Vec<Image>, Image)>();
 
     // start worker threads
     let mut join_handles = Vec::new();
     for _ in 0..opt.thread_count {
         let rx = rx.clone();
         let opt = opt.clone();
+        let dispatcher = dispatcher.clone();
+        let failed_count = failed_count.clone();
 
         let join_handle = std::thread::spawn(move || {
+            // initialize runtime
+            let mut runtime = match tokio::runtime::Builder::new()
+                    .basic_scheduler().enable_all().build() {
+                Ok(runtime) => runtime,
+                Err(e) => panic!("failed to start runtime: {}", e),
+            };
+
             let mut batch = Vec::new();
             for datum in rx.iter() {
                 batch.push(datum);
 
                 if batch.len() == opt.batch_size {
-                    if let Err(e) = process(&batch, &opt) {
+                    if let Err(e) = runtime.block_on(
+                            process(&dispatcher, &batch)) {
                         println!("batch process failed: {}", e);
+                        failed_count.fetch_add(1, Ordering::Relaxed);
                     }
 
                     batch.clear();
                 }
             }
 
-            if batch.len() != 0 {
-                if let Err(e) = process(&batch, &opt) {
+            if !batch.is_empty() {
+                if let Err(e) = runtime.block_on(
+                        process(&dispatcher, &batch)) {
                     println!("batch process failed: {}", e);
+                    failed_count.fetch_add(1, Ordering::Relaxed);
                 }
             }
         });
@@ -118,70 +237,46 @@ fn main() {
     }
 
     // process SATnet images
-    let (mut sentinel2_start_index, mut sentinel2_end_index) = (0, 0);
-    let mut modis_index = 0;
+    let policy = ConfigPolicy::new(selection_config.clone());
+    let window = selection_config.sentinel2_window * 86400;
 
     let instant = Instant::now();
     let mut count = 0;
-    while modis_index < modis_images.len() {
-        // adjust sentinel2 window
-        while sentinel2_start_index + 1 < sentinel2_images.len()
-                && sentinel2_images[sentinel2_start_index].geocode <
-                modis_images[modis_index].geocode {
-            sentinel2_start_index += 1;
-        }
-
-        while sentinel2_start_index + 1 < sentinel2_images.len()
-                && sentinel2_images[sentinel2_start_index+1].geocode == 
-                    modis_images[modis_index].geocode
-                && sentinel2_images[sentinel2_start_index+1].timestamp <
-                    modis_images[modis_index].timestamp {
-            sentinel2_start_index += 1;
-        }
-
-        while sentinel2_images[sentinel2_end_index].geocode <
-                sentinel2_images[sentinel2_start_index].geocode {
-            sentinel2_end_index += 1;
-        }
-        while sentinel2_end_index < sentinel2_start_index
-                && sentinel2_end_index + 1 < sentinel2_images.len()
-                && sentinel2_images[sentinel2_end_index+1].timestamp <
-                    modis_images[modis_index].timestamp - (15 * 86400){
-            sentinel2_end_index += 1;
-        }
-        
-        // check if geocodes are equal
-        if sentinel2_images[sentinel2_start_index].geocode
-                    != modis_images[modis_index].geocode
-                || sentinel2_images[sentinel2_start_index].timestamp
-                    > modis_images[modis_index].timestamp {
-            modis_index += 1;
-            continue;
-        }
-
-        // not enough sentinel2 images -> move to next MODIS image
-        if sentinel2_start_index - sentinel2_end_index < 3 {
-            modis_index += 1;
+    let mut rejected_count = 0;
+    for modis_image in modis_images.iter() {
+        // images are sorted by geohash and timestamp, so candidates are
+        // the sentinel-2 images in the window preceding the modis image
+        let geocode = modis_image.geocode.as_str();
+        let start_index = sentinel2_images.partition_point(|x|
+            (x.geocode.as_str(), x.timestamp)
+                < (geocode, modis_image.timestamp - window));
+        let end_index = sentinel2_images.partition_point(|x|
+            (x.geocode.as_str(), x.timestamp)
+                < (geocode, modis_image.timestamp));
+
+        let candidates: Vec<Image> = sentinel2_images
+            [start_index..end_index].iter().rev().cloned().collect();
+        let (sentinel2_vec, modis_image) = match policy.select_stitch(
+                modis_image.timestamp, &candidates,
+                std::slice::from_ref(modis_image)) {
+            Some(selection) => selection,
+            None => continue,
+        };
+
+        // skip geohashes no server was fit on
+        if !dispatcher.is_routable(&modis_image.geocode) {
+            println!("no stitchd server handles geohash '{}'",
+                modis_image.geocode);
+            rejected_count += 1;
             continue;
         }
 
-        // get images
-        let mut sentinel2_vec = Vec::new();
-        while sentinel2_vec.len() < 3 {
-            let image = &sentinel2_images[sentinel2_start_index
-                - sentinel2_vec.len()];
-            sentinel2_vec.push(image.clone());
-        }
-
-        let modis_image = &modis_images[modis_index];
-
         // send images down channel
-        if let Err(e) = tx.send((sentinel2_vec, modis_image.clone())) {
+        if let Err(e) = tx.send((sentinel2_vec, modis_image)) {
             panic!("failed to send geohash: {}", e);
         }
 
         count += 1;
-        modis_index += 1;
     }
 
     // join worker threads
@@ -193,63 +288,27 @@ fn main() {
     }
 
     let duration = instant.elapsed();
-    println!("imputed {} image(s) in {}.{}", count,
-        duration.as_secs(), duration.subsec_nanos());
+    println!("imputed {} image(s) in {}.{}, rejected {} unroutable",
+        count, duration.as_secs(), duration.subsec_nanos(), rejected_count);
+    println!("{} retries, {} requeued batch(es), {} failed batch(es)",
+        stats.retries(), dispatcher.requeue_count(),
+        failed_count.load(Ordering::Relaxed));
+    for (addr, count) in dispatcher.dispatch_counts() {
+        println!("  {}: {} batch(es)", addr, count);
+    }
 }
 
-fn process(batch: &Vec<(Vec<Image>, Image)>,
-        opt: &Opt) -> Result<(), Box<dyn Error>> {
-    // connect to stitchd service
-    let addr = format!("{}:12289", opt.ip_address);
-    let mut stream = TcpStream::connect(&addr)?;
-
+async fn process(dispatcher: &Dispatcher,
+        batch: &[(Vec<Image>, Image)]) -> Result<(), YogiError> {
     let instant = Instant::now();
 
-    // write batch metadata
-    stream.write_u8(batch.len() as u8)?;
-    for (sentinel2_images, modis_image) in batch.iter() {
-        // write geohash and timestamp
-        write_string(&modis_image.geocode, &mut stream)?;
-        stream.write_i64::<BigEndian>(modis_image.timestamp)?;
-
-        // write paths
-        stream.write_u8(sentinel2_images.len() as u8)?;
-        for image in sentinel2_images.iter() {
-            write_string(&image.files[3].path, &mut stream)?;
-        }
-
-        write_string(&modis_image.files[1].path, &mut stream)?;
-    }
-
-    // check for failure
-    if stream.read_u8()? != 0 {
-        let error_message = read_string(&mut stream)?;
-        return Err(error_message.into())
-    }
-
-    // read datasets
-    for _ in 0..batch.len() {
-        let _dataset = st_image::serialize::read(&mut stream)?;
-    }
+    // impute batch
+    let datasets = dispatcher.impute(batch).await?;
+    let count = datasets.len();
 
     let duration = instant.elapsed();
-    println!("processed batch (size {}) in {}.{}", batch.len(), 
+    println!("processed batch (size {}) in {}.{}", count,
         duration.as_secs(), duration.subsec_nanos());
 
     Ok(())
 }
-
-fn read_string<T: Read>(reader: &mut T)
-        -> Result<String, Box<dyn Error>> {
-    let len = reader.read_u8()?;
-    let mut buf = vec![0u8; len as usize];
-    reader.read_exact(&mut buf)?;
-    Ok(String::from_utf8(buf)?)
-}
-
-fn write_string<T: Write>(value: &str, writer: &mut T)
-        -> Result<(), Box<dyn Error>> {
-    writer.write_u8(value.len() as u8)?;
-    writer.write(value.as_bytes())?;
-    Ok(())
-}
@@ -0,0 +1,200 @@
+use futures::stream::{self, Stream};
+use gdal::Dataset;
+use protobuf::Image;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::YogiError;
+use crate::connection::{self, ConnectionConfig};
+use crate::protocol::{self, Capabilities, DescribeRequest, ImputeBatchRequest, ImputeRequest, Request, Response, StipReadRequest, Version};
+
+use std::io::Cursor;
+use std::net::Shutdown;
+use std::pin::Pin;
+
+pub type DatasetStream =
+    Pin<Box<dyn Stream<Item = Result<Dataset, YogiError>>>>;
+
+/// reads and writes images on a stip node transfer address
+///
+/// stip servers only implement the v0 format, so requests are never
+/// framed regardless of the version used for stitchd
+#[derive(Clone, Debug)]
+pub struct StipClient {
+    addr: String,
+    config: ConnectionConfig,
+}
+
+impl StipClient {
+    pub fn new(addr: String, config: ConnectionConfig) -> StipClient {
+        StipClient {
+            addr,
+            config,
+        }
+    }
+
+    /// read a dataset, retrying transient failures
+    pub async fn read(&self, request: &StipReadRequest)
+            -> Result<Dataset, YogiError> {
+        let mut datasets = self.config
+            .retry(|| self.send(request, 1)).await?;
+        datasets.pop().ok_or_else(|| YogiError::Deserialize(
+            "response contained no dataset".to_string()))
+    }
+
+    async fn send<R: Request>(&self, request: &R, count: usize)
+            -> Result<Vec<Dataset>, YogiError> {
+        let tcp_stream = send_request(&self.config,
+            &self.addr, Version::V0, request).await?;
+        let buf = read_to_end(&self.config, tcp_stream).await?;
+        protocol::read_response(Version::V0, count, &mut Cursor::new(buf))
+    }
+}
+
+/// submits batches of imputation requests to a stitchd server
+#[derive(Clone, Debug)]
+pub struct StitchdClient {
+    addr: String,
+    config: ConnectionConfig,
+    version: Version,
+}
+
+impl StitchdClient {
+    pub fn new(addr: String, version: Version, config: ConnectionConfig)
+            -> StitchdClient {
+        StitchdClient {
+            addr,
+            config,
+            version,
+        }
+    }
+
+    /// query the server capabilities, retrying transient failures
+    pub async fn describe(&self) -> Result<Capabilities, YogiError> {
+        if self.version == Version::V0 {
+            return Err(YogiError::Config(
+                "describe requires protocol v1".to_string()));
+        }
+
+        self.config.retry(|| async {
+            let tcp_stream = send_request(&self.config,
+                &self.addr, self.version, &DescribeRequest).await?;
+            let buf = read_to_end(&self.config, tcp_stream).await?;
+            protocol::read_capabilities(self.version,
+                &mut Cursor::new(buf))
+        }).await
+    }
+
+    /// impute each (sentinel-2 images, modis image) pair in the batch,
+    /// retrying transient failures
+    pub async fn impute(&self, batch: &[(Vec<Image>, Image)])
+            -> Result<Vec<Dataset>, YogiError> {
+        self.config.retry(|| async {
+            use futures::StreamExt;
+            let mut stream = self.impute_stream(batch).await?;
+
+            let mut datasets = Vec::new();
+            while let Some(result) = stream.next().await {
+                datasets.push(result?);
+            }
+
+            Ok(datasets)
+        }).await
+    }
+
+    /// impute a batch, yielding datasets in batch order as they arrive
+    ///
+    /// v0 responses are unframed, so their datasets are only available
+    /// once the server closes the connection. streams are not retried.
+    pub async fn impute_stream(&self, batch: &[(Vec<Image>, Image)])
+            -> Result<DatasetStream, YogiError> {
+        let mut requests = Vec::new();
+        for (sentinel2_images, modis_image) in batch.iter() {
+            requests.push(ImputeRequest::new(sentinel2_images, modis_image)?);
+        }
+
+        let request = ImputeBatchRequest { requests };
+        let mut tcp_stream = send_request(&self.config,
+            &self.addr, self.version, &request).await?;
+
+        let count = batch.len();
+        let read_timeout = self.config.read_timeout;
+        match self.version {
+            Version::V0 => {
+                let buf = read_to_end(&self.config, tcp_stream).await?;
+
+                let datasets = match Response::decode(self.version,
+                        count, &mut Cursor::new(buf))? {
+                    Response::Datasets(datasets) => datasets,
+                    Response::Error(message) =>
+                        return Err(YogiError::Remote(message)),
+                };
+
+                Ok(Box::pin(stream::iter(datasets.into_iter().map(Ok))))
+            },
+            Version::V1 => {
+                // validate handshake
+                let mut magic = [0u8; 4];
+                connection::timeout(read_timeout, "read",
+                    tcp_stream.read_exact(&mut magic)).await?;
+                let version = connection::timeout(read_timeout, "read",
+                    tcp_stream.read_u8()).await?;
+                if protocol::check_handshake(magic, version)?
+                        != self.version {
+                    return Err(YogiError::Deserialize(format!(
+                        "unexpected protocol version {}", version)));
+                }
+
+                // decode each frame as it is received
+                Ok(Box::pin(stream::unfold((tcp_stream, count),
+                    move |(mut tcp_stream, count)| async move {
+                        if count == 0 {
+                            return None;
+                        }
+
+                        // stop reading after the first failure
+                        let result = connection::timeout(read_timeout,
+                                "read", async {
+                            let mut header = [0u8; 5];
+                            tcp_stream.read_exact(&mut header).await?;
+                            let (message_type, length) =
+                                protocol::frame_header(header)?;
+
+                            let mut payload = vec![0u8; length];
+                            tcp_stream.read_exact(&mut payload).await?;
+                            protocol::decode_frame(message_type, payload)
+                        }).await;
+                        let count = match result {
+                            Ok(_) => count - 1,
+                            Err(_) => 0,
+                        };
+
+                        Some((result, (tcp_stream, count)))
+                    })))
+            },
+        }
+    }
+}
+
+/// connect, send a request, and close the write half of the connection
+/// so the server reads the request to its end
+async fn send_request<R: Request>(config: &ConnectionConfig, addr: &str,
+        version: Version, request: &R) -> Result<TcpStream, YogiError> {
+    let mut buf = Vec::new();
+    protocol::write_request(version, request, &mut buf)?;
+
+    let mut tcp_stream = config.connect(addr).await?;
+    connection::timeout(config.write_timeout, "write",
+        tcp_stream.write_all(&buf)).await?;
+    tcp_stream.shutdown(Shutdown::Write)?;
+    Ok(tcp_stream)
+}
+
+/// read an entire response, which ends when the server closes
+async fn read_to_end(config: &ConnectionConfig,
+        mut tcp_stream: TcpStream) -> Result<Vec<u8>, YogiError> {
+    let mut buf = Vec::new();
+    connection::timeout(config.read_timeout, "read",
+        tcp_stream.read_to_end(&mut buf)).await?;
+    Ok(buf)
+}
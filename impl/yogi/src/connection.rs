@@ -0,0 +1,146 @@
+use structopt::StructOpt;
+use tokio::net::TcpStream;
+
+use crate::YogiError;
+
+use std::future::Future;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// timeouts and retry behavior for transfer protocol connections
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    /// attempts made after the first for idempotent requests
+    pub retries: u32,
+    /// delay before the first retry, doubling with each attempt
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub stats: Arc<RetryStats>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> ConnectionConfig {
+        ConnectionConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(300),
+            write_timeout: Duration::from_secs(60),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            stats: Arc::new(RetryStats::default()),
+        }
+    }
+}
+
+/// transfer connection command line options shared by the binaries
+#[derive(Clone, Debug, StructOpt)]
+pub struct ConnectionOpt {
+    #[structopt(long, help="transfer connect timeout in seconds",
+        default_value="10")]
+    pub connect_timeout: u64,
+
+    #[structopt(long, help="transfer read timeout in seconds",
+        default_value="300")]
+    pub read_timeout: u64,
+
+    #[structopt(long, help="retry count for failed transfers",
+        default_value="3")]
+    pub retries: u32,
+
+    #[structopt(long, help="initial retry backoff in milliseconds",
+        default_value="500")]
+    pub retry_backoff: u64,
+
+    #[structopt(long, help="transfer write timeout in seconds",
+        default_value="60")]
+    pub write_timeout: u64,
+}
+
+impl ConnectionOpt {
+    pub fn config(&self) -> ConnectionConfig {
+        ConnectionConfig {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            read_timeout: Duration::from_secs(self.read_timeout),
+            write_timeout: Duration::from_secs(self.write_timeout),
+            retries: self.retries,
+            backoff: Duration::from_millis(self.retry_backoff),
+            ..ConnectionConfig::default()
+        }
+    }
+}
+
+impl ConnectionConfig {
+    pub async fn connect(&self, addr: &str)
+            -> Result<TcpStream, YogiError> {
+        timeout(self.connect_timeout, "connect", TcpStream::connect(addr))
+            .await
+    }
+
+    /// run operation, retrying transient failures with exponential backoff
+    pub async fn retry<T, F, Fut>(&self, mut operation: F)
+            -> Result<T, YogiError>
+            where F: FnMut() -> Fut,
+                Fut: Future<Output = Result<T, YogiError>> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Err(ref e) if e.is_transient() && attempt < self.retries => {
+                    tokio::time::delay_for(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    attempt += 1;
+
+                    self.stats.retries.fetch_add(1, Ordering::Relaxed);
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// counts of retried and rerouted requests, shared across tasks
+#[derive(Debug, Default)]
+pub struct RetryStats {
+    retries: AtomicUsize,
+    failovers: AtomicUsize,
+    relookups: AtomicUsize,
+}
+
+impl RetryStats {
+    pub fn retries(&self) -> usize {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn failovers(&self) -> usize {
+        self.failovers.load(Ordering::Relaxed)
+    }
+
+    pub fn record_failover(&self) {
+        self.failovers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// requests retried on a node found by looking a geohash up again
+    pub fn relookups(&self) -> usize {
+        self.relookups.load(Ordering::Relaxed)
+    }
+
+    pub fn record_relookup(&self) {
+        self.relookups.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// fail with a TimedOut io error if future does not complete in time
+pub(crate) async fn timeout<T, E, F>(duration: Duration, operation: &str,
+        future: F) -> Result<T, YogiError>
+        where F: Future<Output = Result<T, E>>, YogiError: From<E> {
+    match tokio::time::timeout(duration, future).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(YogiError::Io(std::io::Error::new(ErrorKind::TimedOut,
+            format!("{} timed out after {:?}", operation, duration)))),
+    }
+}
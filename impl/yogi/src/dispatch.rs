@@ -0,0 +1,261 @@
+use gdal::Dataset;
+use protobuf::Image;
+
+use crate::{ConnectionConfig, Endpoint, StitchdClient, YogiError};
+use crate::protocol::Version;
+
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// server state changes reported to the caller's event hook
+#[derive(Debug)]
+pub enum DispatchEvent<'a> {
+    /// a batch failed on addr and is requeued on another server
+    Requeued { addr: &'a str, error: &'a YogiError },
+    /// an unhealthy server answered its health check
+    Restored { addr: &'a str },
+}
+
+type EventHook = Box<dyn Fn(DispatchEvent) + Send + Sync>;
+
+/// spreads imputation batches across stitchd servers, sending each to
+/// the healthy server handling its geohashes with the fewest outstanding
+/// batches
+pub struct Dispatcher {
+    addrs: Vec<String>,
+    clients: Vec<StitchdClient>,
+    config: ConnectionConfig,
+    endpoints: Vec<Endpoint>,
+    event_hook: Option<EventHook>,
+    health_interval: Duration,
+    probes: Vec<StitchdClient>,
+    requeue_count: AtomicUsize,
+    servers: Mutex<Vec<ServerState>>,
+}
+
+#[derive(Default)]
+struct ServerState {
+    dispatched: usize,
+    outstanding: usize,
+    unhealthy_since: Option<Instant>,
+}
+
+impl Dispatcher {
+    pub fn new(endpoints: Vec<Endpoint>, version: Version,
+            config: ConnectionConfig, health_interval: Duration)
+            -> Dispatcher {
+        let addrs: Vec<String> =
+            endpoints.iter().map(|x| x.addr()).collect();
+
+        // failed batches are requeued on another server rather than
+        // retried against the same one
+        let client_config = ConnectionConfig {
+            retries: 0,
+            ..config.clone()
+        };
+
+        let clients = addrs.iter().map(|addr| StitchdClient::new(
+            addr.clone(), version, client_config.clone())).collect();
+
+        // every stitchd server answers v1 describe requests, whichever
+        // version its batches are sent in
+        let probes = addrs.iter().map(|addr| StitchdClient::new(
+            addr.clone(), Version::V1, client_config.clone())).collect();
+        let servers = addrs.iter().map(|_| ServerState::default()).collect();
+
+        Dispatcher {
+            addrs,
+            clients,
+            config,
+            endpoints,
+            event_hook: None,
+            health_interval,
+            probes,
+            requeue_count: AtomicUsize::new(0),
+            servers: Mutex::new(servers),
+        }
+    }
+
+    /// report requeued batches and restored servers to hook
+    pub fn on_event<F>(mut self, hook: F) -> Dispatcher
+            where F: Fn(DispatchEvent) + Send + Sync + 'static {
+        self.event_hook = Some(Box::new(hook));
+        self
+    }
+
+    fn report(&self, event: DispatchEvent) {
+        if let Some(hook) = &self.event_hook {
+            hook(event);
+        }
+    }
+
+    /// whether any server handles geohash
+    pub fn is_routable(&self, geohash: &str) -> bool {
+        self.endpoints.iter().any(|x| x.handles(geohash))
+    }
+
+    /// largest batch accepted by any server, none if unknown
+    pub fn max_batch_size(&self) -> Option<usize> {
+        self.endpoints.iter()
+            .map(|x| x.capabilities.as_ref()
+                .map(|y| y.max_batch_size as usize))
+            .max().flatten()
+    }
+
+    /// impute a batch, splitting it by geohash if no single server
+    /// accepts all of its geohashes
+    pub async fn impute(&self, batch: &[(Vec<Image>, Image)])
+            -> Result<Vec<Dataset>, YogiError> {
+        if self.endpoints.iter().any(|x| x.accepts(batch).is_ok()) {
+            return self.impute_routed(batch).await;
+        }
+
+        let geohashes: Vec<&str> = batch.iter()
+            .map(|(_, modis_image)| modis_image.geocode.as_str()).collect();
+
+        // group batch indices by geohash
+        let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+        for (i, geohash) in geohashes.iter().enumerate() {
+            match groups.iter_mut().find(|(x, _)| x == geohash) {
+                Some((_, indices)) => indices.push(i),
+                None => groups.push((geohash, vec![i])),
+            }
+        }
+
+        let mut datasets: Vec<Option<Dataset>> =
+            batch.iter().map(|_| None).collect();
+        for (_, indices) in groups {
+            let group_batch: Vec<(Vec<Image>, Image)> = indices.iter()
+                .map(|i| batch[*i].clone()).collect();
+            let group_datasets = self.impute_routed(&group_batch).await?;
+
+            for (i, dataset) in indices.into_iter().zip(group_datasets) {
+                datasets[i] = Some(dataset);
+            }
+        }
+
+        datasets.into_iter().map(|x| x.ok_or_else(||
+            YogiError::Deserialize(
+                "response contained too few datasets".to_string())))
+            .collect()
+    }
+
+    /// impute a batch on a server accepting it, requeueing it on another
+    /// server if its server fails, and waiting for a health check if
+    /// every such server has failed
+    async fn impute_routed(&self, batch: &[(Vec<Image>, Image)])
+            -> Result<Vec<Dataset>, YogiError> {
+        // validate the batch before sending it anywhere
+        let mut accepted = Err(YogiError::Config(
+            "no stitchd servers configured".to_string()));
+        for endpoint in self.endpoints.iter() {
+            accepted = endpoint.accepts(batch);
+            if accepted.is_ok() {
+                break;
+            }
+        }
+
+        accepted?;
+
+        let mut wait_count = 0;
+        loop {
+            self.check_health().await;
+
+            let index = match self.acquire(batch) {
+                Some(index) => index,
+                None if wait_count < self.config.retries => {
+                    wait_count += 1;
+                    tokio::time::delay_for(self.health_interval).await;
+                    continue;
+                },
+                None => return Err(YogiError::Io(std::io::Error::new(
+                    ErrorKind::NotConnected,
+                    "no healthy stitchd servers".to_string()))),
+            };
+
+            let result = self.clients[index].impute(batch).await;
+            let failed = match &result {
+                Err(e) => e.is_transient(),
+                Ok(_) => false,
+            };
+
+            self.release(index, failed);
+            if !failed {
+                return result;
+            }
+
+            if let Err(e) = &result {
+                self.report(DispatchEvent::Requeued {
+                    addr: &self.addrs[index],
+                    error: e,
+                });
+            }
+
+            self.requeue_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// batches dispatched to each server
+    pub fn dispatch_counts(&self) -> Vec<(String, usize)> {
+        let servers = self.servers.lock().unwrap();
+        self.addrs.iter().cloned()
+            .zip(servers.iter().map(|x| x.dispatched)).collect()
+    }
+
+    pub fn requeue_count(&self) -> usize {
+        self.requeue_count.load(Ordering::Relaxed)
+    }
+
+    fn acquire(&self, batch: &[(Vec<Image>, Image)]) -> Option<usize> {
+        let mut servers = self.servers.lock().unwrap();
+        let index = servers.iter().enumerate()
+            .filter(|(_, x)| x.unhealthy_since.is_none())
+            .filter(|(i, _)| self.endpoints[*i].accepts(batch).is_ok())
+            .min_by_key(|(_, x)| x.outstanding)
+            .map(|(i, _)| i)?;
+
+        servers[index].dispatched += 1;
+        servers[index].outstanding += 1;
+        Some(index)
+    }
+
+    fn release(&self, index: usize, failed: bool) {
+        let mut servers = self.servers.lock().unwrap();
+        servers[index].outstanding -= 1;
+        if failed {
+            servers[index].unhealthy_since = Some(Instant::now());
+        }
+    }
+
+    /// probe servers marked unhealthy for at least the health interval,
+    /// restoring those which answer a describe request
+    async fn check_health(&self) {
+        let now = Instant::now();
+        let mut indices = Vec::new();
+        {
+            let mut servers = self.servers.lock().unwrap();
+            for (i, server) in servers.iter_mut().enumerate() {
+                match server.unhealthy_since {
+                    Some(instant) if now.duration_since(instant)
+                            >= self.health_interval => {
+                        // reset so concurrent callers skip this server
+                        server.unhealthy_since = Some(now);
+                        indices.push(i);
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        for index in indices {
+            if self.probes[index].describe().await.is_ok() {
+                self.servers.lock().unwrap()[index].unhealthy_since = None;
+                self.report(DispatchEvent::Restored {
+                    addr: &self.addrs[index],
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use protobuf::Image;
+
+use crate::{ConnectionConfig, StitchdClient, YogiError};
+use crate::protocol::{Capabilities, Version};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// port of the stitchd server assumed on each stip host when no
+/// endpoints are configured
+pub const DEFAULT_STITCHD_PORT: u16 = 12289;
+
+/// stitchd server address and the geohashes its model was fit on
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    /// geohashes the model was fit on, empty if unknown, in which case
+    /// any geohash is accepted
+    pub geohashes: Vec<String>,
+    /// reported by the server, none until described
+    pub capabilities: Option<Capabilities>,
+}
+
+impl Endpoint {
+    pub fn new(host: String, port: u16) -> Endpoint {
+        Endpoint {
+            host,
+            port,
+            geohashes: Vec::new(),
+            capabilities: None,
+        }
+    }
+
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn handles(&self, geohash: &str) -> bool {
+        let configured = handles_geohash(&self.geohashes, geohash);
+        match &self.capabilities {
+            Some(capabilities) => configured && capabilities.handles(geohash),
+            None => configured,
+        }
+    }
+
+    /// check that a batch may be sent to this endpoint
+    pub fn accepts(&self, batch: &[(Vec<Image>, Image)])
+            -> Result<(), YogiError> {
+        if let Some((_, modis_image)) = batch.iter()
+                .find(|(_, x)| !self.handles(&x.geocode)) {
+            return Err(YogiError::Config(format!(
+                "{} does not handle geohash '{}'",
+                self.addr(), modis_image.geocode)));
+        }
+
+        match &self.capabilities {
+            Some(capabilities) => capabilities.validate(batch),
+            None => Ok(()),
+        }
+    }
+
+    /// query and store the server capabilities
+    pub async fn describe(&mut self, version: Version,
+            config: &ConnectionConfig) -> Result<(), YogiError> {
+        let client = StitchdClient::new(self.addr(), version, config.clone());
+        self.capabilities = Some(client.describe().await?);
+        Ok(())
+    }
+}
+
+/// whether a model fit on geohashes handles geohash. servers encode
+/// each image by its parent geohash (the geohash less its last
+/// character), so that parent must be listed exactly. an empty list is
+/// unknown and handles any geohash.
+pub(crate) fn handles_geohash(geohashes: &[String], geohash: &str) -> bool {
+    if geohashes.is_empty() {
+        return true;
+    }
+
+    match geohash.char_indices().last() {
+        Some((i, _)) => geohashes.iter().any(|x| *x == geohash[..i]),
+        None => false,
+    }
+}
+
+/// stitchd imputation endpoints, typically read from 'etc/hosts.txt'
+#[derive(Clone, Debug, Default)]
+pub struct EndpointRegistry {
+    endpoints: Vec<Endpoint>,
+}
+
+impl EndpointRegistry {
+    /// parse 'host port [options]' lines, where options are passed to
+    /// the server by the start scripts and only '-g/--geohash' options
+    /// are interpreted here
+    pub fn from_hosts_file(path: &Path)
+            -> Result<EndpointRegistry, YogiError> {
+        let file = File::open(path).map_err(|e| YogiError::Config(
+            format!("failed to open '{}': {}", path.display(), e)))?;
+
+        let mut endpoints = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let port = match fields.get(1).map(|x| x.parse::<u16>()) {
+                Some(Ok(port)) => port,
+                _ => return Err(YogiError::Config(format!(
+                    "invalid line {}: '{}'", i + 1, line))),
+            };
+
+            let mut endpoint = Endpoint::new(fields[0].to_string(), port);
+            let mut options = fields.iter().skip(2);
+            while let Some(option) = options.next() {
+                if *option == "-g" || *option == "--geohash" {
+                    match options.next() {
+                        Some(geohash) =>
+                            endpoint.geohashes.push(geohash.to_string()),
+                        None => return Err(YogiError::Config(format!(
+                            "line {}: missing geohash after '{}'",
+                            i + 1, option))),
+                    }
+                } else if let Some(geohash) =
+                        option.strip_prefix("--geohash=") {
+                    endpoint.geohashes.push(geohash.to_string());
+                }
+            }
+
+            endpoints.push(endpoint);
+        }
+
+        if endpoints.is_empty() {
+            return Err(YogiError::Config(format!(
+                "no endpoints in '{}'", path.display())));
+        }
+
+        Ok(EndpointRegistry { endpoints })
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// query the capabilities of each endpoint, returning the addresses
+    /// of those which could not be described
+    pub async fn describe(&mut self, version: Version,
+            config: &ConnectionConfig) -> Vec<(String, YogiError)> {
+        let mut failures = Vec::new();
+        for endpoint in self.endpoints.iter_mut() {
+            if let Err(e) = endpoint.describe(version, config).await {
+                failures.push((endpoint.addr(), e));
+            }
+        }
+
+        failures
+    }
+
+    /// whether any configured endpoint handles geohash, always true when
+    /// no endpoints are configured
+    pub fn is_routable(&self, geohash: &str) -> bool {
+        self.endpoints.is_empty()
+            || self.endpoints.iter().any(|x| x.handles(geohash))
+    }
+
+    /// addresses of endpoints accepting an imputation request for a stip
+    /// node, preferring those on the node host, ordered so that each
+    /// geohash starts at a stable endpoint. empty if no endpoint accepts
+    /// the request.
+    pub fn resolve(&self, xfer_addr: &str, sentinel2_images: &[Image],
+            modis_image: &Image) -> Vec<String> {
+        let host = xfer_addr.split(':').next().unwrap_or(xfer_addr);
+        if self.endpoints.is_empty() {
+            return vec![format!("{}:{}", host, DEFAULT_STITCHD_PORT)];
+        }
+
+        let batch = [(sentinel2_images.to_vec(), modis_image.clone())];
+        let endpoints: Vec<&Endpoint> = self.endpoints.iter()
+            .filter(|x| x.accepts(&batch).is_ok()).collect();
+        let mut addrs: Vec<String> = endpoints.iter()
+            .filter(|x| x.host == host).map(|x| x.addr()).collect();
+        if addrs.is_empty() {
+            addrs = endpoints.iter().map(|x| x.addr()).collect();
+        }
+
+        if addrs.is_empty() {
+            return addrs;
+        }
+
+        // spread geohashes across endpoints
+        let hash = crate::fnv1a(modis_image.geocode.as_bytes());
+        let len = addrs.len();
+        addrs.rotate_left((hash % len as u64) as usize);
+        addrs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::Image;
+
+    use super::{DEFAULT_STITCHD_PORT, Endpoint, EndpointRegistry};
+
+    use std::path::PathBuf;
+
+    fn hosts_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "yogi-hosts-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn load(name: &str, contents: &str)
+            -> Result<EndpointRegistry, crate::YogiError> {
+        let path = hosts_file(name, contents);
+        let result = EndpointRegistry::from_hosts_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn image(geocode: &str) -> Image {
+        Image {
+            cloud_coverage: None,
+            files: Vec::new(),
+            geocode: geocode.to_string(),
+            platform: "MODIS".to_string(),
+            source: "test".to_string(),
+            timestamp: 1534095541,
+        }
+    }
+
+    fn resolve(registry: &EndpointRegistry, geocode: &str) -> Vec<String> {
+        registry.resolve("10.0.0.1:15616", &[], &image(geocode))
+    }
+
+    #[test]
+    fn from_hosts_file() {
+        let registry = load("valid", "# stitchd servers\n\n\
+            10.0.0.1 12289 -g 9xj3 --geohash 9xj6 # comment\n\
+            10.0.0.2 12290 -b 64 --geohash=9xj3 -m model.json\n\
+            10.0.0.3 12291\n").unwrap();
+
+        let endpoints = registry.endpoints();
+        assert_eq!(endpoints.len(), 3);
+        assert_eq!(endpoints[0].addr(), "10.0.0.1:12289");
+        assert_eq!(endpoints[0].geohashes, vec!["9xj3", "9xj6"]);
+        assert_eq!(endpoints[1].addr(), "10.0.0.2:12290");
+        assert_eq!(endpoints[1].geohashes, vec!["9xj3"]);
+        assert!(endpoints[2].geohashes.is_empty());
+    }
+
+    #[test]
+    fn from_hosts_file_rejects_malformed_lines() {
+        assert!(load("port", "10.0.0.1\n").is_err());
+        assert!(load("number", "10.0.0.1 port\n").is_err());
+        assert!(load("range", "10.0.0.1 65536\n").is_err());
+        assert!(load("geohash", "10.0.0.1 12289 -g\n").is_err());
+        assert!(load("empty", "# no servers\n").is_err());
+
+        let path = std::env::temp_dir().join("yogi-hosts-missing.txt");
+        assert!(EndpointRegistry::from_hosts_file(&path).is_err());
+    }
+
+    #[test]
+    fn resolve_prefers_node_host() {
+        let registry = load("host", "10.0.0.2 12289\n\
+            10.0.0.1 12289\n10.0.0.1 12290\n10.0.0.3 12289 -g 9xj6\n")
+            .unwrap();
+
+        // endpoints on the stip node host are used when they accept
+        let mut addrs = resolve(&registry, "9xj3e");
+        addrs.sort();
+        assert_eq!(addrs, vec!["10.0.0.1:12289", "10.0.0.1:12290"]);
+
+        // otherwise any accepting endpoint is used
+        let registry = load("remote", "10.0.0.2 12289 -g 9xj3\n\
+            10.0.0.1 12289 -g 9xj6\n").unwrap();
+        assert_eq!(resolve(&registry, "9xj3e"), vec!["10.0.0.2:12289"]);
+        assert!(resolve(&registry, "9xj4e").is_empty());
+
+        // without endpoints the default port on the node host is used
+        assert_eq!(resolve(&EndpointRegistry::default(), "9xj3e"),
+            vec![format!("10.0.0.1:{}", DEFAULT_STITCHD_PORT)]);
+    }
+
+    #[test]
+    fn resolve_rotation() {
+        let registry = load("rotation", "10.0.0.1 12289\n\
+            10.0.0.1 12290\n10.0.0.1 12291\n").unwrap();
+
+        // each geohash starts at a stable endpoint, followed by the rest
+        // in order
+        let addrs = resolve(&registry, "9xj3e");
+        assert_eq!(addrs, resolve(&registry, "9xj3e"));
+        assert_eq!(addrs.len(), 3);
+
+        let start = (crate::fnv1a(b"9xj3e") % 3) as usize;
+        let expected: Vec<String> = (0..3).map(|i| format!(
+            "10.0.0.1:{}", 12289 + (start + i) % 3)).collect();
+        assert_eq!(addrs, expected);
+
+        // geohashes are spread across endpoints
+        let mut starts: Vec<String> = ["9xj3e", "9xj3f", "9xj3g", "9xj3h",
+                "9xj3j", "9xj3k"].iter()
+            .map(|x| resolve(&registry, x)[0].clone()).collect();
+        starts.sort();
+        starts.dedup();
+        assert!(starts.len() > 1);
+    }
+
+    #[test]
+    fn handles_parent_geohash() {
+        let mut endpoint = Endpoint::new("localhost".to_string(), 12289);
+        assert!(endpoint.handles("9xj3e"));
+
+        endpoint.geohashes = vec!["9xj3".to_string()];
+        assert!(endpoint.handles("9xj3e"));
+        assert!(!endpoint.handles("9xj3"));
+        assert!(!endpoint.handles("9xj3ef"));
+        assert!(!endpoint.handles("9xj4e"));
+        assert!(!endpoint.handles(""));
+    }
+}
@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum YogiError {
+    /// failed to establish a grpc connection
+    Transport(tonic::transport::Error),
+    /// a grpc call returned an error status
+    Rpc(tonic::Status),
+    /// network failure on a transfer protocol connection
+    Io(std::io::Error),
+    /// error message reported by a stip or stitchd server
+    Remote(String),
+    /// failed to decode a transfer protocol response
+    Deserialize(String),
+    /// failed to encode a transfer protocol request
+    Serialize(String),
+    /// an image has no file containing the requested bands
+    MissingBands(String),
+    /// invalid endpoint configuration
+    Config(String),
+}
+
+impl Display for YogiError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            YogiError::Transport(e) =>
+                write!(f, "grpc transport error: {}", e),
+            YogiError::Rpc(status) =>
+                write!(f, "grpc request failed: {}", status),
+            YogiError::Io(e) => write!(f, "io error: {}", e),
+            YogiError::Remote(msg) => write!(f, "remote error: {}", msg),
+            YogiError::Deserialize(msg) =>
+                write!(f, "deserialization error: {}", msg),
+            YogiError::Serialize(msg) =>
+                write!(f, "serialization error: {}", msg),
+            YogiError::MissingBands(msg) =>
+                write!(f, "missing bands: {}", msg),
+            YogiError::Config(msg) =>
+                write!(f, "configuration error: {}", msg),
+        }
+    }
+}
+
+impl YogiError {
+    /// network failures which may succeed if the request is repeated
+    pub fn is_transient(&self) -> bool {
+        matches!(self, YogiError::Transport(_) | YogiError::Io(_))
+    }
+}
+
+impl Error for YogiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            YogiError::Transport(e) => Some(e),
+            YogiError::Rpc(status) => Some(status),
+            YogiError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for YogiError {
+    fn from(err: tonic::transport::Error) -> YogiError {
+        YogiError::Transport(err)
+    }
+}
+
+impl From<tonic::Status> for YogiError {
+    fn from(status: tonic::Status) -> YogiError {
+        YogiError::Rpc(status)
+    }
+}
+
+impl From<std::io::Error> for YogiError {
+    fn from(err: std::io::Error) -> YogiError {
+        YogiError::Io(err)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for YogiError {
+    fn from(err: std::string::FromUtf8Error) -> YogiError {
+        YogiError::Deserialize(err.to_string())
+    }
+}
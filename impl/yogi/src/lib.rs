@@ -1,30 +1,28 @@
-use protobuf::{Filter, Image, ImageListRequest, ImageManagementClient};
-use tonic::Request;
+use protobuf::{Filter, Image};
+
+mod band;
+pub use band::{BandLayout, BandSet, modis_file};
+mod client;
+pub use client::{DatasetStream, StipClient, StitchdClient};
+mod connection;
+pub use connection::{ConnectionConfig, ConnectionOpt, RetryStats};
+mod dispatch;
+pub use dispatch::{DispatchEvent, Dispatcher};
+mod endpoint;
+pub use endpoint::{DEFAULT_STITCHD_PORT, Endpoint, EndpointRegistry};
+mod error;
+pub use error::YogiError;
+mod pool;
+pub use pool::ClientPool;
+pub mod protocol;
+mod selection;
+pub use selection::{ConfigPolicy, DefaultPolicy, SelectionConfig, SelectionPolicy, Weights};
 
 use std::cmp::Ordering;
-use std::error::Error;
-
-#[tokio::main]
-pub async fn get_images(album: &str, filter: Filter, rpc_address: &str)
-        -> Result<Vec<Image>, Box<dyn Error>> {
-    // initialize ImageManagement grpc client
-    let mut client = ImageManagementClient::connect(
-        format!("http://{}", rpc_address)).await?;
-
-    // initialize ImageListRequest
-    let request = ImageListRequest {
-        album: album.to_string(),
-        filter: filter,
-    };
-
-    // iterate over image stream
-    let mut stream = client.list(Request::new(request.clone()))
-        .await?.into_inner();
-
-    let mut images = Vec::new();
-    while let Some(image) = stream.message().await? {
-        images.push(image);
-    }
+
+pub async fn get_images(pool: &ClientPool, album: &str, filter: Filter,
+        rpc_address: &str) -> Result<Vec<Image>, YogiError> {
+    let mut images = pool.list_images(rpc_address, album, filter).await?;
 
     // sort images by geohash and timestamp (ascending)
     images.sort_by(|a, b| {
@@ -37,3 +35,26 @@ pub async fn get_images(album: &str, filter: Filter, rpc_address: &str)
 
     Ok(images)
 }
+
+/// 64-bit fnv-1a hash, stable across builds and platforms unlike
+/// DefaultHasher
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn fnv1a() {
+        // reference values of the 64-bit fnv-1a specification
+        assert_eq!(super::fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(super::fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(super::fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
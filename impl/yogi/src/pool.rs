@@ -0,0 +1,106 @@
+use protobuf::{Filter, Image, ImageListRequest, ImageManagementClient, Node, NodeLocateRequest, NodeManagementClient};
+use tokio::sync::Mutex;
+use tonic::Request;
+use tonic::transport::Channel;
+
+use crate::YogiError;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+/// grpc clients shared across requests, keyed by node rpc address
+#[derive(Default)]
+pub struct ClientPool {
+    image_clients: ClientMap<ImageManagementClient<Channel>>,
+    node_clients: ClientMap<NodeManagementClient<Channel>>,
+}
+
+impl ClientPool {
+    pub fn new() -> ClientPool {
+        ClientPool::default()
+    }
+
+    pub async fn image_client(&self, rpc_addr: &str)
+            -> Result<ImageManagementClient<Channel>, YogiError> {
+        self.image_clients.get(rpc_addr,
+            ImageManagementClient::connect).await
+    }
+
+    pub async fn node_client(&self, rpc_addr: &str)
+            -> Result<NodeManagementClient<Channel>, YogiError> {
+        self.node_clients.get(rpc_addr,
+            NodeManagementClient::connect).await
+    }
+
+    pub async fn list_images(&self, rpc_addr: &str, album: &str,
+            filter: Filter) -> Result<Vec<Image>, YogiError> {
+        let mut client = self.image_client(rpc_addr).await?;
+
+        // initialize ImageListRequest
+        let request = ImageListRequest {
+            album: album.to_string(),
+            filter,
+        };
+
+        // iterate over image stream
+        let mut stream = client.list(Request::new(request))
+            .await?.into_inner();
+
+        let mut images = Vec::new();
+        while let Some(image) = stream.message().await? {
+            images.push(image);
+        }
+
+        Ok(images)
+    }
+
+    pub async fn locate(&self, rpc_addr: &str, geocode: &str)
+            -> Result<Option<Node>, YogiError> {
+        let mut client = self.node_client(rpc_addr).await?;
+
+        // initialize NodeLocateRequest
+        let request = Request::new(NodeLocateRequest {
+            geocode: geocode.to_string(),
+        });
+
+        // retrieve NodeLocateReply
+        let reply = client.locate(request).await?;
+        Ok(reply.into_inner().node)
+    }
+}
+
+/// clients connected at most once per address, where only requests to
+/// the same address wait on a connection in progress
+struct ClientMap<T> {
+    clients: std::sync::Mutex<HashMap<String, Arc<Mutex<Option<T>>>>>,
+}
+
+impl<T> Default for ClientMap<T> {
+    fn default() -> ClientMap<T> {
+        ClientMap {
+            clients: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> ClientMap<T> {
+    async fn get<F, Fut, E>(&self, rpc_addr: &str, connect: F)
+            -> Result<T, YogiError>
+            where F: FnOnce(String) -> Fut,
+                Fut: Future<Output = Result<T, E>>, YogiError: From<E> {
+        let slot = self.clients.lock().unwrap()
+            .entry(rpc_addr.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(None)))
+            .clone();
+
+        let mut client = slot.lock().await;
+        if let Some(client) = client.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let connected = connect(format!("http://{}", rpc_addr)).await?;
+        *client = Some(connected.clone());
+        Ok(connected)
+    }
+}
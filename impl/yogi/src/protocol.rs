@@ -0,0 +1,846 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use gdal::Dataset;
+use protobuf::Image;
+
+use crate::YogiError;
+use crate::band::{self, BandSet};
+use crate::endpoint;
+
+use std::io::{Cursor, Read, Write};
+use std::str::FromStr;
+
+/// prefix identifying a versioned connection, v0 requests never begin
+/// with 0xFF because it is neither a stip op nor a valid batch size
+pub const MAGIC: [u8; 4] = [0xFF, b'S', b'T', b'X'];
+
+/// gdal data type code of byte bands
+pub const GDT_BYTE: u32 = 1;
+
+/// largest v1 frame accepted, bounding the buffer allocated for a
+/// length read from the connection
+pub const MAX_FRAME_SIZE: usize = 1 << 30;
+
+/// largest v1 string accepted
+pub const MAX_STRING_SIZE: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    /// unframed messages with u8 string lengths
+    V0,
+    /// handshake followed by typed frames with u32 lengths
+    V1,
+}
+
+impl Version {
+    fn as_u8(&self) -> u8 {
+        match self {
+            Version::V0 => 0,
+            Version::V1 => 1,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Version, String> {
+        match s {
+            "0" => Ok(Version::V0),
+            "1" => Ok(Version::V1),
+            _ => Err(format!("unsupported protocol version '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageType {
+    StipRead,
+    ImputeBatch,
+    Dataset,
+    Error,
+    Describe,
+    Capabilities,
+}
+
+impl MessageType {
+    fn as_u8(&self) -> u8 {
+        match self {
+            MessageType::StipRead => 0,
+            MessageType::ImputeBatch => 1,
+            MessageType::Dataset => 2,
+            MessageType::Error => 3,
+            MessageType::Describe => 5,
+            MessageType::Capabilities => 6,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Result<MessageType, YogiError> {
+        match value {
+            0 => Ok(MessageType::StipRead),
+            1 => Ok(MessageType::ImputeBatch),
+            2 => Ok(MessageType::Dataset),
+            3 => Ok(MessageType::Error),
+            5 => Ok(MessageType::Describe),
+            6 => Ok(MessageType::Capabilities),
+            _ => Err(YogiError::Deserialize(
+                format!("unknown message type {}", value))),
+        }
+    }
+}
+
+pub trait Request: Sized {
+    const MESSAGE_TYPE: MessageType;
+
+    /// write the request payload
+    fn encode<T: Write>(&self, version: Version,
+        writer: &mut T) -> Result<(), YogiError>;
+
+    /// read the request payload
+    fn decode<T: Read>(version: Version,
+        reader: &mut T) -> Result<Self, YogiError>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StipReadRequest {
+    pub path: String,
+    /// read only the region of the image covered by this geocode
+    pub subgeocode: Option<String>,
+}
+
+impl StipReadRequest {
+    pub fn new(path: String) -> StipReadRequest {
+        StipReadRequest {
+            path,
+            subgeocode: None,
+        }
+    }
+}
+
+impl Request for StipReadRequest {
+    const MESSAGE_TYPE: MessageType = MessageType::StipRead;
+
+    fn encode<T: Write>(&self, version: Version,
+            writer: &mut T) -> Result<(), YogiError> {
+        // write readop and path
+        writer.write_u8(0)?;
+        write_string(version, &self.path, writer)?;
+
+        // write subgeocode indicator and subgeocode
+        match &self.subgeocode {
+            Some(subgeocode) => {
+                writer.write_u8(1)?;
+                write_string(version, subgeocode, writer)?;
+            },
+            None => writer.write_u8(0)?,
+        }
+
+        Ok(())
+    }
+
+    fn decode<T: Read>(version: Version,
+            reader: &mut T) -> Result<StipReadRequest, YogiError> {
+        let op = reader.read_u8()?;
+        if op != 0 {
+            return Err(YogiError::Deserialize(
+                format!("unsupported stip op {}", op)));
+        }
+
+        let path = read_string(version, reader)?;
+        let subgeocode = match reader.read_u8()? {
+            0 => None,
+            _ => Some(read_string(version, reader)?),
+        };
+
+        Ok(StipReadRequest {
+            path,
+            subgeocode,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImputeRequest {
+    pub geocode: String,
+    pub timestamp: i64,
+    pub sentinel2_paths: Vec<String>,
+    pub modis_path: String,
+}
+
+impl ImputeRequest {
+    pub fn new(sentinel2_images: &[Image], modis_image: &Image)
+            -> Result<ImputeRequest, YogiError> {
+        // imputation operates on true color sentinel-2 images
+        let mut sentinel2_paths = Vec::new();
+        for image in sentinel2_images.iter() {
+            sentinel2_paths.push(BandSet::TrueColor.path(image)?);
+        }
+
+        Ok(ImputeRequest {
+            geocode: modis_image.geocode.clone(),
+            timestamp: modis_image.timestamp,
+            sentinel2_paths,
+            modis_path: band::modis_path(modis_image)?,
+        })
+    }
+
+    fn encode<T: Write>(&self, version: Version,
+            writer: &mut T) -> Result<(), YogiError> {
+        if self.sentinel2_paths.len() > u8::MAX as usize {
+            return Err(YogiError::Serialize(format!(
+                "{} sentinel-2 paths exceeds 255",
+                self.sentinel2_paths.len())));
+        }
+
+        // write geohash and timestamp
+        write_string(version, &self.geocode, writer)?;
+        writer.write_i64::<BigEndian>(self.timestamp)?;
+
+        // write paths
+        writer.write_u8(self.sentinel2_paths.len() as u8)?;
+        for path in self.sentinel2_paths.iter() {
+            write_string(version, path, writer)?;
+        }
+
+        write_string(version, &self.modis_path, writer)
+    }
+
+    fn decode<T: Read>(version: Version,
+            reader: &mut T) -> Result<ImputeRequest, YogiError> {
+        let geocode = read_string(version, reader)?;
+        let timestamp = reader.read_i64::<BigEndian>()?;
+
+        let count = reader.read_u8()?;
+        let mut sentinel2_paths = Vec::new();
+        for _ in 0..count {
+            sentinel2_paths.push(read_string(version, reader)?);
+        }
+
+        let modis_path = read_string(version, reader)?;
+        Ok(ImputeRequest {
+            geocode,
+            timestamp,
+            sentinel2_paths,
+            modis_path,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImputeBatchRequest {
+    pub requests: Vec<ImputeRequest>,
+}
+
+impl Request for ImputeBatchRequest {
+    const MESSAGE_TYPE: MessageType = MessageType::ImputeBatch;
+
+    fn encode<T: Write>(&self, version: Version,
+            writer: &mut T) -> Result<(), YogiError> {
+        // a v0 batch size of 255 is indistinguishable from the magic
+        if self.requests.len() >= 255 {
+            return Err(YogiError::Serialize(format!(
+                "batch size {} exceeds 254", self.requests.len())));
+        }
+
+        writer.write_u8(self.requests.len() as u8)?;
+        for request in self.requests.iter() {
+            request.encode(version, writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn decode<T: Read>(version: Version,
+            reader: &mut T) -> Result<ImputeBatchRequest, YogiError> {
+        let count = reader.read_u8()?;
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            requests.push(ImputeRequest::decode(version, reader)?);
+        }
+
+        Ok(ImputeBatchRequest { requests })
+    }
+}
+
+/// query the capabilities of a stitchd server, only supported in v1
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DescribeRequest;
+
+impl Request for DescribeRequest {
+    const MESSAGE_TYPE: MessageType = MessageType::Describe;
+
+    fn encode<T: Write>(&self, version: Version,
+            _writer: &mut T) -> Result<(), YogiError> {
+        match version {
+            Version::V0 => Err(YogiError::Serialize(
+                "describe requires protocol v1".to_string())),
+            Version::V1 => Ok(()),
+        }
+    }
+
+    fn decode<T: Read>(_version: Version,
+            _reader: &mut T) -> Result<DescribeRequest, YogiError> {
+        Ok(DescribeRequest)
+    }
+}
+
+/// imputation capabilities reported by a stitchd server
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    pub model_version: String,
+    pub max_batch_size: u8,
+    /// geohashes the model was fit on, empty if any are accepted
+    pub geohashes: Vec<String>,
+    /// gdal data types of supported sentinel-2 bands
+    pub data_types: Vec<u32>,
+}
+
+impl Capabilities {
+    /// servers encode each image by its parent geohash, which must be
+    /// one of the listed geohashes
+    pub fn handles(&self, geohash: &str) -> bool {
+        endpoint::handles_geohash(&self.geohashes, geohash)
+    }
+
+    /// check that a batch of imputation requests may be sent
+    pub fn validate(&self, batch: &[(Vec<Image>, Image)])
+            -> Result<(), YogiError> {
+        if batch.len() > self.max_batch_size as usize {
+            return Err(YogiError::Config(format!(
+                "batch size {} exceeds server maximum {}",
+                batch.len(), self.max_batch_size)));
+        }
+
+        if let Some((_, modis_image)) = batch.iter()
+                .find(|(_, x)| !self.handles(&x.geocode)) {
+            return Err(YogiError::Config(format!(
+                "server does not handle geohash '{}'",
+                modis_image.geocode)));
+        }
+
+        // imputation operates on true color sentinel-2 images
+        if !self.data_types.contains(&GDT_BYTE) {
+            return Err(YogiError::Config(
+                "server does not support byte bands".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn encode<T: Write>(&self, version: Version,
+            writer: &mut T) -> Result<(), YogiError> {
+        if self.geohashes.len() > u32::MAX as usize
+                || self.data_types.len() > u8::MAX as usize {
+            return Err(YogiError::Serialize(
+                "capabilities exceed field lengths".to_string()));
+        }
+
+        write_string(version, &self.model_version, writer)?;
+        writer.write_u8(self.max_batch_size)?;
+
+        writer.write_u32::<BigEndian>(self.geohashes.len() as u32)?;
+        for geohash in self.geohashes.iter() {
+            write_string(version, geohash, writer)?;
+        }
+
+        writer.write_u8(self.data_types.len() as u8)?;
+        for data_type in self.data_types.iter() {
+            writer.write_u32::<BigEndian>(*data_type)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn decode<T: Read>(version: Version,
+            reader: &mut T) -> Result<Capabilities, YogiError> {
+        let model_version = read_string(version, reader)?;
+        let max_batch_size = reader.read_u8()?;
+
+        let count = reader.read_u32::<BigEndian>()?;
+        let mut geohashes = Vec::new();
+        for _ in 0..count {
+            geohashes.push(read_string(version, reader)?);
+        }
+
+        let count = reader.read_u8()?;
+        let mut data_types = Vec::new();
+        for _ in 0..count {
+            data_types.push(reader.read_u32::<BigEndian>()?);
+        }
+
+        Ok(Capabilities {
+            model_version,
+            max_batch_size,
+            geohashes,
+            data_types,
+        })
+    }
+}
+
+pub enum Response {
+    Datasets(Vec<Dataset>),
+    Error(String),
+}
+
+impl Response {
+    pub fn encode<T: Write>(&self, version: Version,
+            writer: &mut T) -> Result<(), YogiError> {
+        if version == Version::V1 {
+            write_handshake(version, writer)?;
+        }
+
+        match (self, version) {
+            (Response::Datasets(datasets), Version::V0) => {
+                writer.write_u8(0)?;
+                for dataset in datasets.iter() {
+                    write_dataset(dataset, writer)?;
+                }
+            },
+            (Response::Datasets(datasets), Version::V1) => {
+                for dataset in datasets.iter() {
+                    let mut buf = Vec::new();
+                    write_dataset(dataset, &mut buf)?;
+                    write_frame(MessageType::Dataset, &buf, writer)?;
+                }
+            },
+            (Response::Error(message), Version::V0) => {
+                writer.write_u8(1)?;
+                write_string(version, message, writer)?;
+            },
+            (Response::Error(message), Version::V1) => write_frame(
+                MessageType::Error, message.as_bytes(), writer)?,
+        }
+
+        Ok(())
+    }
+
+    /// read a response containing the expected number of datasets
+    pub fn decode<T: Read>(version: Version, count: usize,
+            reader: &mut T) -> Result<Response, YogiError> {
+        let mut datasets = Vec::new();
+        match version {
+            Version::V0 => {
+                // check for failure
+                if reader.read_u8()? != 0 {
+                    let message = read_string(version, reader)?;
+                    return Ok(Response::Error(message));
+                }
+
+                // read datasets
+                for _ in 0..count {
+                    datasets.push(read_dataset(reader)?);
+                }
+            },
+            Version::V1 => {
+                read_handshake(version, reader)?;
+
+                // read length-prefixed dataset frames
+                while datasets.len() < count {
+                    let (message_type, payload) = read_frame(reader)?;
+                    match decode_frame(message_type, payload) {
+                        Ok(dataset) => datasets.push(dataset),
+                        Err(YogiError::Remote(message)) =>
+                            return Ok(Response::Error(message)),
+                        Err(e) => return Err(e),
+                    }
+                }
+            },
+        }
+
+        Ok(Response::Datasets(datasets))
+    }
+}
+
+/// write a request, preceded by the handshake and frame header in v1
+pub fn write_request<R: Request, T: Write>(version: Version,
+        request: &R, writer: &mut T) -> Result<(), YogiError> {
+    match version {
+        Version::V0 => request.encode(version, writer),
+        Version::V1 => {
+            let mut buf = Vec::new();
+            request.encode(version, &mut buf)?;
+
+            write_handshake(version, writer)?;
+            write_frame(R::MESSAGE_TYPE, &buf, writer)
+        },
+    }
+}
+
+/// read a request, detecting the protocol version used by the client
+pub fn read_request<R: Request, T: Read>(reader: &mut T)
+        -> Result<(Version, R), YogiError> {
+    let first = reader.read_u8()?;
+    if first != MAGIC[0] {
+        // v0 requests are unframed, so replay the byte already read
+        let mut reader = Cursor::new([first]).chain(reader);
+        let request = R::decode(Version::V0, &mut reader)?;
+        return Ok((Version::V0, request));
+    }
+
+    let mut magic = [first, 0, 0, 0];
+    reader.read_exact(&mut magic[1..])?;
+    let version = check_handshake(magic, reader.read_u8()?)?;
+
+    let (message_type, payload) = read_frame(reader)?;
+    if message_type != R::MESSAGE_TYPE {
+        return Err(YogiError::Deserialize(format!(
+            "expected {:?} message, found {:?}",
+            R::MESSAGE_TYPE, message_type)));
+    }
+
+    let request = R::decode(version, &mut Cursor::new(payload))?;
+    Ok((version, request))
+}
+
+/// write a capabilities response to a describe request
+pub fn write_capabilities<T: Write>(version: Version,
+        capabilities: &Capabilities, writer: &mut T)
+        -> Result<(), YogiError> {
+    if version == Version::V0 {
+        return Err(YogiError::Serialize(
+            "describe requires protocol v1".to_string()));
+    }
+
+    let mut buf = Vec::new();
+    capabilities.encode(version, &mut buf)?;
+
+    write_handshake(version, writer)?;
+    write_frame(MessageType::Capabilities, &buf, writer)
+}
+
+/// read a capabilities response, converting remote failures into errors
+pub fn read_capabilities<T: Read>(version: Version,
+        reader: &mut T) -> Result<Capabilities, YogiError> {
+    read_handshake(version, reader)?;
+
+    let (message_type, payload) = read_frame(reader)?;
+    match message_type {
+        MessageType::Capabilities =>
+            Capabilities::decode(version, &mut Cursor::new(payload)),
+        MessageType::Error => Err(YogiError::Remote(
+            String::from_utf8(payload)?)),
+        _ => Err(YogiError::Deserialize(format!(
+            "unexpected {:?} message", message_type))),
+    }
+}
+
+/// read a response, converting remote failures into errors
+pub fn read_response<T: Read>(version: Version, count: usize,
+        reader: &mut T) -> Result<Vec<Dataset>, YogiError> {
+    match Response::decode(version, count, reader)? {
+        Response::Datasets(datasets) => Ok(datasets),
+        Response::Error(message) => Err(YogiError::Remote(message)),
+    }
+}
+
+fn write_handshake<T: Write>(version: Version,
+        writer: &mut T) -> Result<(), YogiError> {
+    writer.write_all(&MAGIC)?;
+    writer.write_u8(version.as_u8())?;
+    Ok(())
+}
+
+fn read_handshake<T: Read>(version: Version,
+        reader: &mut T) -> Result<(), YogiError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let remote_version = check_handshake(magic, reader.read_u8()?)?;
+    if remote_version != version {
+        return Err(YogiError::Deserialize(format!(
+            "expected protocol {:?}, found {:?}", version, remote_version)));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_handshake(magic: [u8; 4], version: u8)
+        -> Result<Version, YogiError> {
+    if magic != MAGIC {
+        return Err(YogiError::Deserialize(
+            "invalid protocol handshake".to_string()));
+    }
+
+    match version {
+        1 => Ok(Version::V1),
+        _ => Err(YogiError::Deserialize(
+            format!("unsupported protocol version {}", version))),
+    }
+}
+
+fn write_frame<T: Write>(message_type: MessageType,
+        payload: &[u8], writer: &mut T) -> Result<(), YogiError> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(YogiError::Serialize(format!(
+            "frame length {} exceeds {}", payload.len(), MAX_FRAME_SIZE)));
+    }
+
+    writer.write_u8(message_type.as_u8())?;
+    writer.write_u32::<BigEndian>(payload.len() as u32)?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+fn read_frame<T: Read>(reader: &mut T)
+        -> Result<(MessageType, Vec<u8>), YogiError> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let (message_type, length) = frame_header(header)?;
+
+    let mut buf = vec![0u8; length];
+    reader.read_exact(&mut buf)?;
+    Ok((message_type, buf))
+}
+
+/// parse a v1 frame header, a message type and u32 payload length
+pub(crate) fn frame_header(header: [u8; 5])
+        -> Result<(MessageType, usize), YogiError> {
+    let message_type = MessageType::from_u8(header[0])?;
+    let length = u32::from_be_bytes([header[1], header[2],
+        header[3], header[4]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(YogiError::Deserialize(format!(
+            "frame length {} exceeds {}", length, MAX_FRAME_SIZE)));
+    }
+
+    Ok((message_type, length))
+}
+
+/// decode a v1 response frame into a dataset
+pub(crate) fn decode_frame(message_type: MessageType,
+        payload: Vec<u8>) -> Result<Dataset, YogiError> {
+    match message_type {
+        MessageType::Dataset => read_dataset(&mut Cursor::new(payload)),
+        MessageType::Error => Err(YogiError::Remote(
+            String::from_utf8(payload)?)),
+        _ => Err(YogiError::Deserialize(format!(
+            "unexpected {:?} message", message_type))),
+    }
+}
+
+fn read_dataset<T: Read>(reader: &mut T) -> Result<Dataset, YogiError> {
+    st_image::serialize::read(reader)
+        .map_err(|e| YogiError::Deserialize(e.to_string()))
+}
+
+fn write_dataset<T: Write>(dataset: &Dataset,
+        writer: &mut T) -> Result<(), YogiError> {
+    st_image::serialize::write(dataset, writer)
+        .map_err(|e| YogiError::Serialize(e.to_string()))
+}
+
+pub fn read_string<T: Read>(version: Version, reader: &mut T)
+        -> Result<String, YogiError> {
+    let len = match version {
+        Version::V0 => reader.read_u8()? as usize,
+        Version::V1 => reader.read_u32::<BigEndian>()? as usize,
+    };
+
+    if len > MAX_STRING_SIZE {
+        return Err(YogiError::Deserialize(format!(
+            "string length {} exceeds {}", len, MAX_STRING_SIZE)));
+    }
+
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+pub fn write_string<T: Write>(version: Version, value: &str,
+        writer: &mut T) -> Result<(), YogiError> {
+    // refuse to truncate values which do not fit the length field
+    match version {
+        Version::V0 if value.len() > u8::MAX as usize =>
+            return Err(YogiError::Serialize(format!(
+                "'{}' exceeds 255 bytes, use protocol v1", value))),
+        Version::V0 => writer.write_u8(value.len() as u8)?,
+        Version::V1 if value.len() > MAX_STRING_SIZE =>
+            return Err(YogiError::Serialize(format!(
+                "string length {} exceeds {}",
+                value.len(), MAX_STRING_SIZE))),
+        Version::V1 => writer.write_u32::<BigEndian>(value.len() as u32)?,
+    }
+
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::*;
+
+    fn impute_request(geocode: &str) -> ImputeRequest {
+        ImputeRequest {
+            geocode: geocode.to_string(),
+            timestamp: 1534095541,
+            sentinel2_paths: vec!["/data/0.tif".to_string(),
+                "/data/1.tif".to_string()],
+            modis_path: "/data/2.tif".to_string(),
+        }
+    }
+
+    fn round_trip<R: Request>(version: Version, request: &R)
+            -> (Version, R) {
+        let mut buf = Vec::new();
+        write_request(version, request, &mut buf).unwrap();
+        read_request(&mut Cursor::new(buf)).unwrap()
+    }
+
+    #[test]
+    fn stip_read_request_round_trip() {
+        for version in [Version::V0, Version::V1].iter() {
+            let mut request =
+                StipReadRequest::new("/data/0.tif".to_string());
+            assert_eq!(round_trip(*version, &request),
+                (*version, request.clone()));
+
+            request.subgeocode = Some("9xj3e".to_string());
+            assert_eq!(round_trip(*version, &request), (*version, request));
+        }
+    }
+
+    #[test]
+    fn impute_batch_request_round_trip() {
+        for version in [Version::V0, Version::V1].iter() {
+            let request = ImputeBatchRequest {
+                requests: vec![impute_request("9xj3e"),
+                    impute_request("9xj3f")],
+            };
+            assert_eq!(round_trip(*version, &request), (*version, request));
+        }
+    }
+
+    #[test]
+    fn impute_batch_size_guard() {
+        let mut request = ImputeBatchRequest {
+            requests: vec![impute_request("9xj3e"); 254],
+        };
+        for version in [Version::V0, Version::V1].iter() {
+            assert!(write_request(*version,
+                &request, &mut Vec::new()).is_ok());
+        }
+
+        // a v0 batch of 255 would begin with the magic byte
+        request.requests.push(impute_request("9xj3e"));
+        for version in [Version::V0, Version::V1].iter() {
+            assert!(matches!(write_request(*version,
+                &request, &mut Vec::new()), Err(YogiError::Serialize(_))));
+        }
+    }
+
+    #[test]
+    fn read_request_detects_version() {
+        // v0 requests begin with a stip op or batch size
+        let buf = vec![0, 1, b'a', 0];
+        let (version, request): (Version, StipReadRequest) =
+            read_request(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(version, Version::V0);
+        assert_eq!(request.path, "a");
+
+        let mut buf = Vec::new();
+        write_request(Version::V1, &DescribeRequest, &mut buf).unwrap();
+        assert_eq!(buf[..4], MAGIC);
+        let (version, _): (Version, DescribeRequest) =
+            read_request(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(version, Version::V1);
+
+        // an unknown version or mismatched message type is rejected
+        let buf = vec![0xFF, b'S', b'T', b'X', 2, 5, 0, 0, 0, 0];
+        assert!(read_request::<DescribeRequest, _>(
+            &mut Cursor::new(buf)).is_err());
+
+        let mut buf = Vec::new();
+        write_request(Version::V1, &DescribeRequest, &mut buf).unwrap();
+        assert!(read_request::<StipReadRequest, _>(
+            &mut Cursor::new(buf)).is_err());
+    }
+
+    #[test]
+    fn response_round_trip() {
+        for version in [Version::V0, Version::V1].iter() {
+            let mut buf = Vec::new();
+            Response::Error("model failed".to_string())
+                .encode(*version, &mut buf).unwrap();
+            match Response::decode(*version, 2, &mut Cursor::new(buf)) {
+                Ok(Response::Error(message)) =>
+                    assert_eq!(message, "model failed"),
+                _ => panic!("expected an error response"),
+            }
+
+            let mut buf = Vec::new();
+            Response::Datasets(Vec::new())
+                .encode(*version, &mut buf).unwrap();
+            match Response::decode(*version, 0, &mut Cursor::new(buf)) {
+                Ok(Response::Datasets(datasets)) =>
+                    assert!(datasets.is_empty()),
+                _ => panic!("expected a dataset response"),
+            }
+        }
+    }
+
+    #[test]
+    fn capabilities_round_trip() {
+        let capabilities = Capabilities {
+            model_version: "weights.h5".to_string(),
+            max_batch_size: 64,
+            geohashes: vec!["9xj3".to_string(), "9xj6".to_string()],
+            data_types: vec![GDT_BYTE],
+        };
+
+        for version in [Version::V0, Version::V1].iter() {
+            let mut buf = Vec::new();
+            capabilities.encode(*version, &mut buf).unwrap();
+            assert_eq!(Capabilities::decode(*version,
+                &mut Cursor::new(buf)).unwrap(), capabilities);
+        }
+
+        // describe responses are only framed in v1
+        assert!(write_capabilities(Version::V0,
+            &capabilities, &mut Vec::new()).is_err());
+
+        let mut buf = Vec::new();
+        write_capabilities(Version::V1, &capabilities, &mut buf).unwrap();
+        assert_eq!(read_capabilities(Version::V1,
+            &mut Cursor::new(buf)).unwrap(), capabilities);
+    }
+
+    #[test]
+    fn string_length_limits() {
+        // v0 lengths are a single byte
+        let value = "a".repeat(255);
+        let mut buf = Vec::new();
+        write_string(Version::V0, &value, &mut buf).unwrap();
+        assert_eq!(read_string(Version::V0,
+            &mut Cursor::new(buf)).unwrap(), value);
+
+        let value = "a".repeat(256);
+        assert!(matches!(write_string(Version::V0, &value, &mut Vec::new()),
+            Err(YogiError::Serialize(_))));
+        assert!(write_string(Version::V1, &value, &mut Vec::new()).is_ok());
+
+        let value = "a".repeat(MAX_STRING_SIZE + 1);
+        assert!(write_string(Version::V1, &value, &mut Vec::new()).is_err());
+
+        // oversize lengths are rejected before reading the string
+        let mut buf = Vec::new();
+        buf.write_u32::<BigEndian>(MAX_STRING_SIZE as u32 + 1).unwrap();
+        assert!(matches!(read_string(Version::V1, &mut Cursor::new(buf)),
+            Err(YogiError::Deserialize(_))));
+    }
+
+    #[test]
+    fn frame_length_limit() {
+        let mut buf = vec![MessageType::Dataset.as_u8()];
+        buf.write_u32::<BigEndian>(u32::MAX).unwrap();
+        assert!(matches!(read_frame(&mut Cursor::new(buf)),
+            Err(YogiError::Deserialize(_))));
+
+        let mut buf = vec![MessageType::Error.as_u8()];
+        buf.write_u32::<BigEndian>(2).unwrap();
+        buf.extend_from_slice(b"ok");
+        let (message_type, payload) =
+            read_frame(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(message_type, MessageType::Error);
+        assert_eq!(payload, b"ok");
+    }
+}
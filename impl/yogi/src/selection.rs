@@ -0,0 +1,486 @@
+use protobuf::{Filter, Image};
+
+use crate::YogiError;
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+pub trait SelectionPolicy: Debug + Send + Sync {
+    /// filter used to retrieve candidate sentinel-2 images
+    fn sentinel2_filter(&self, geohash: &str, timestamp: i64) -> Filter;
+
+    /// filter used to retrieve candidate modis images
+    fn modis_filter(&self, geohash: &str, timestamp: i64) -> Filter;
+
+    /// choose a sentinel-2 image to download directly from stip
+    fn select_stip(&self, timestamp: i64,
+        sentinel2_images: &[Image]) -> Option<Image>;
+
+    /// choose the sentinel-2 and modis images used for imputation
+    fn select_stitch(&self, timestamp: i64, sentinel2_images: &[Image],
+        modis_images: &[Image]) -> Option<(Vec<Image>, Image)>;
+}
+
+fn end_of_day(timestamp: i64) -> i64 {
+    timestamp + (86400 - (timestamp % 86400))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DefaultPolicy {}
+
+impl SelectionPolicy for DefaultPolicy {
+    fn sentinel2_filter(&self, geohash: &str, timestamp: i64) -> Filter {
+        let end_timestamp = end_of_day(timestamp);
+        Filter {
+            end_timestamp: Some(end_timestamp),
+            geocode: Some(geohash.to_string()),
+            max_cloud_coverage: None,
+            min_pixel_coverage: Some(1.0),
+            platform: Some("Sentinel-2".to_string()),
+            recurse: false,
+            source: None,
+            start_timestamp: Some(end_timestamp - (15 * 86400) + 1),
+        }
+    }
+
+    fn modis_filter(&self, geohash: &str, timestamp: i64) -> Filter {
+        let end_timestamp = end_of_day(timestamp);
+        Filter {
+            end_timestamp: Some(end_timestamp),
+            geocode: Some(geohash.to_string()),
+            max_cloud_coverage: None,
+            min_pixel_coverage: None,
+            platform: Some("MODIS".to_string()),
+            recurse: false,
+            source: None,
+            start_timestamp: Some(end_timestamp - (10 * 86400) + 1),
+        }
+    }
+
+    // images are retrieved in descending order by timestamp, so the
+    // most recent candidates are selected
+    fn select_stip(&self, timestamp: i64,
+            sentinel2_images: &[Image]) -> Option<Image> {
+        sentinel2_images.iter()
+            .find(|x| (x.timestamp - timestamp).abs() <= 86400)
+            .cloned()
+    }
+
+    fn select_stitch(&self, _timestamp: i64, sentinel2_images: &[Image],
+            modis_images: &[Image]) -> Option<(Vec<Image>, Image)> {
+        if sentinel2_images.len() >= 2 && !modis_images.is_empty() {
+            Some((sentinel2_images[..2].to_vec(), modis_images[0].clone()))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Weights {
+    pub cloud_coverage: f64,
+    pub pixel_coverage: f64,
+    pub temporal_distance: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            cloud_coverage: 1.0,
+            pixel_coverage: 1.0,
+            temporal_distance: 1.0,
+        }
+    }
+}
+
+/// score an image in [0, 1] where higher values indicate better quality
+pub fn score(image: &Image, timestamp: i64,
+        window: i64, weights: &Weights) -> f64 {
+    // cloud coverage is reported as a percentage, unknown coverage is
+    // assumed to be complete so it never outranks a measured image
+    let cloud_coverage = image.cloud_coverage.unwrap_or(100.0) / 100.0;
+    let pixel_coverage = image.files.iter()
+        .map(|x| x.pixel_coverage).fold(1.0, f64::min);
+    let temporal_distance = (image.timestamp - timestamp).abs() as f64
+        / (window.max(1) * 86400) as f64;
+
+    let total = weights.cloud_coverage
+        + weights.pixel_coverage + weights.temporal_distance;
+    if total <= 0.0 {
+        return 0.0;
+    }
+
+    (weights.cloud_coverage * (1.0 - cloud_coverage.clamp(0.0, 1.0))
+        + weights.pixel_coverage * pixel_coverage.clamp(0.0, 1.0)
+        + weights.temporal_distance
+            * (1.0 - temporal_distance.min(1.0))) / total
+}
+
+/// sort images in descending order by score
+pub fn rank(mut images: Vec<Image>, timestamp: i64,
+        window: i64, weights: &Weights) -> Vec<Image> {
+    images.sort_by(|a, b| {
+        let (a, b) = (score(a, timestamp, window, weights),
+            score(b, timestamp, window, weights));
+        b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    images
+}
+
+// imputation expects images in descending order by timestamp
+fn by_timestamp(images: &[Image]) -> Vec<Image> {
+    let mut images = images.to_vec();
+    images.sort_by_key(|x| std::cmp::Reverse(x.timestamp));
+    images
+}
+
+#[derive(Clone, Debug)]
+pub struct SelectionConfig {
+    /// days of sentinel-2 imagery considered before the timestamp
+    pub sentinel2_window: i64,
+    /// days of modis imagery considered before the timestamp
+    pub modis_window: i64,
+    /// seconds between an image and the timestamp to use it directly
+    pub tolerance: i64,
+    pub sentinel2_min_pixel_coverage: Option<f64>,
+    pub sentinel2_max_cloud_coverage: Option<f64>,
+    pub modis_min_pixel_coverage: Option<f64>,
+    /// number of sentinel-2 images required for imputation
+    pub sentinel2_count: usize,
+    /// number of modis images required for imputation
+    pub modis_count: usize,
+    /// weights used to rank candidate sentinel-2 images
+    pub weights: Weights,
+    /// minimum score for an image to be used directly
+    pub min_score: Option<f64>,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> SelectionConfig {
+        SelectionConfig {
+            sentinel2_window: 15,
+            modis_window: 10,
+            tolerance: 86400,
+            sentinel2_min_pixel_coverage: Some(1.0),
+            sentinel2_max_cloud_coverage: None,
+            modis_min_pixel_coverage: None,
+            sentinel2_count: 2,
+            modis_count: 1,
+            weights: Weights::default(),
+            min_score: None,
+        }
+    }
+}
+
+impl SelectionConfig {
+    pub fn from_file(path: &Path) -> Result<SelectionConfig, YogiError> {
+        let file = File::open(path).map_err(|e| YogiError::Config(
+            format!("failed to open '{}': {}", path.display(), e)))?;
+
+        // parse 'key = value' lines, ignoring comments
+        let mut config = SelectionConfig::default();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.splitn(2, '=')
+                .map(|x| x.trim()).collect();
+            if fields.len() != 2 {
+                return Err(YogiError::Config(format!(
+                    "invalid line {}: '{}'", i + 1, line)));
+            }
+
+            config.set(fields[0], fields[1]).map_err(|e|
+                YogiError::Config(format!("line {}: {}", i + 1, e)))?;
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+            value.parse::<T>()
+                .map_err(|_| format!("invalid value '{}'", value))
+        }
+
+        fn parse_option(value: &str) -> Result<Option<f64>, String> {
+            match value {
+                "none" => Ok(None),
+                _ => parse::<f64>(value).map(Some),
+            }
+        }
+
+        match key {
+            "sentinel2_window" => self.sentinel2_window = parse(value)?,
+            "modis_window" => self.modis_window = parse(value)?,
+            "tolerance" => self.tolerance = parse(value)?,
+            "sentinel2_min_pixel_coverage" =>
+                self.sentinel2_min_pixel_coverage = parse_option(value)?,
+            "sentinel2_max_cloud_coverage" =>
+                self.sentinel2_max_cloud_coverage = parse_option(value)?,
+            "modis_min_pixel_coverage" =>
+                self.modis_min_pixel_coverage = parse_option(value)?,
+            "sentinel2_count" => self.sentinel2_count = parse(value)?,
+            "modis_count" => self.modis_count = parse(value)?,
+            "cloud_coverage_weight" =>
+                self.weights.cloud_coverage = parse(value)?,
+            "pixel_coverage_weight" =>
+                self.weights.pixel_coverage = parse(value)?,
+            "temporal_distance_weight" =>
+                self.weights.temporal_distance = parse(value)?,
+            "min_score" => self.min_score = parse_option(value)?,
+            _ => return Err(format!("unknown key '{}'", key)),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigPolicy {
+    config: SelectionConfig,
+}
+
+impl ConfigPolicy {
+    pub fn new(config: SelectionConfig) -> ConfigPolicy {
+        ConfigPolicy { config }
+    }
+}
+
+impl SelectionPolicy for ConfigPolicy {
+    fn sentinel2_filter(&self, geohash: &str, timestamp: i64) -> Filter {
+        let end_timestamp = end_of_day(timestamp);
+        Filter {
+            end_timestamp: Some(end_timestamp),
+            geocode: Some(geohash.to_string()),
+            max_cloud_coverage: self.config.sentinel2_max_cloud_coverage,
+            min_pixel_coverage: self.config.sentinel2_min_pixel_coverage,
+            platform: Some("Sentinel-2".to_string()),
+            recurse: false,
+            source: None,
+            start_timestamp: Some(end_timestamp
+                - (self.config.sentinel2_window * 86400) + 1),
+        }
+    }
+
+    fn modis_filter(&self, geohash: &str, timestamp: i64) -> Filter {
+        let end_timestamp = end_of_day(timestamp);
+        Filter {
+            end_timestamp: Some(end_timestamp),
+            geocode: Some(geohash.to_string()),
+            max_cloud_coverage: None,
+            min_pixel_coverage: self.config.modis_min_pixel_coverage,
+            platform: Some("MODIS".to_string()),
+            recurse: false,
+            source: None,
+            start_timestamp: Some(end_timestamp
+                - (self.config.modis_window * 86400) + 1),
+        }
+    }
+
+    fn select_stip(&self, timestamp: i64,
+            sentinel2_images: &[Image]) -> Option<Image> {
+        let candidates: Vec<Image> = sentinel2_images.iter()
+            .filter(|x| (x.timestamp - timestamp).abs()
+                <= self.config.tolerance)
+            .cloned().collect();
+
+        // prefer imputation if the best candidate scores too low
+        let image = rank(candidates, timestamp,
+                self.config.sentinel2_window, &self.config.weights)
+            .into_iter().next()?;
+        match self.config.min_score {
+            Some(min_score) if score(&image, timestamp,
+                    self.config.sentinel2_window, &self.config.weights)
+                < min_score => None,
+            _ => Some(image),
+        }
+    }
+
+    fn select_stitch(&self, timestamp: i64, sentinel2_images: &[Image],
+            modis_images: &[Image]) -> Option<(Vec<Image>, Image)> {
+        let (sentinel2_count, modis_count) =
+            (self.config.sentinel2_count, self.config.modis_count.max(1));
+        if sentinel2_images.len() >= sentinel2_count
+                && modis_images.len() >= modis_count {
+            let images = rank(sentinel2_images.to_vec(), timestamp,
+                self.config.sentinel2_window, &self.config.weights);
+            Some((by_timestamp(&images[..sentinel2_count]),
+                modis_images[0].clone()))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::{File, Image};
+
+    use super::{ConfigPolicy, SelectionConfig, SelectionPolicy, Weights};
+
+    use std::path::PathBuf;
+
+    fn image(timestamp: i64, cloud_coverage: f64,
+            pixel_coverage: f64) -> Image {
+        Image {
+            cloud_coverage: Some(cloud_coverage),
+            files: vec![File {
+                description: "True color image, UTM 13N".to_string(),
+                path: format!("/data/{}.tif", timestamp),
+                pixel_coverage,
+                subdataset: 0,
+            }],
+            geocode: "9xj3e".to_string(),
+            platform: "Sentinel-2".to_string(),
+            source: "test".to_string(),
+            timestamp,
+        }
+    }
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "yogi-selection-{}-{}.conf", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn load(name: &str, contents: &str)
+            -> Result<SelectionConfig, crate::YogiError> {
+        let path = config_file(name, contents);
+        let result = SelectionConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn from_file() {
+        let config = load("valid", "# comment\n\n\
+            sentinel2_window = 30 # trailing comment\n\
+            sentinel2_min_pixel_coverage = none\n\
+            sentinel2_max_cloud_coverage=20.5\n\
+            cloud_coverage_weight = 2\n").unwrap();
+        assert_eq!(config.sentinel2_window, 30);
+        assert_eq!(config.sentinel2_min_pixel_coverage, None);
+        assert_eq!(config.sentinel2_max_cloud_coverage, Some(20.5));
+        assert_eq!(config.weights.cloud_coverage, 2.0);
+
+        // unset keys keep their defaults
+        assert_eq!(config.modis_window, 10);
+        assert_eq!(config.sentinel2_count, 2);
+    }
+
+    #[test]
+    fn from_file_rejects_invalid_lines() {
+        assert!(load("unknown", "sentinel2_windw = 30\n").is_err());
+        assert!(load("value", "sentinel2_count = two\n").is_err());
+        assert!(load("option", "min_score = high\n").is_err());
+        assert!(load("separator", "tolerance 86400\n").is_err());
+        let path = std::env::temp_dir().join("yogi-selection-missing.conf");
+        assert!(SelectionConfig::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn rank() {
+        let images = vec![
+            image(1534000000, 80.0, 1.0),
+            image(1534086400, 0.0, 1.0),
+            image(1534000000, 0.0, 0.5),
+        ];
+
+        let weights = Weights::default();
+        let ranked = super::rank(images, 1534086400, 15, &weights);
+        let order: Vec<(i64, Option<f64>)> = ranked.iter()
+            .map(|x| (x.timestamp, x.cloud_coverage)).collect();
+        assert_eq!(order, vec![(1534086400, Some(0.0)),
+            (1534000000, Some(0.0)), (1534000000, Some(80.0))]);
+
+        // weights select which quality dominates
+        let weights = Weights {
+            cloud_coverage: 0.0,
+            pixel_coverage: 1.0,
+            temporal_distance: 0.0,
+        };
+        let ranked = super::rank(ranked, 1534086400, 15, &weights);
+        assert_eq!(ranked[2].files[0].pixel_coverage, 0.5);
+    }
+
+    #[test]
+    fn score_unknown_cloud_coverage() {
+        let weights = Weights::default();
+        let mut unknown = image(1534086400, 0.0, 1.0);
+        unknown.cloud_coverage = None;
+
+        let cloudy = image(1534086400, 100.0, 1.0);
+        assert_eq!(super::score(&unknown, 1534086400, 15, &weights),
+            super::score(&cloudy, 1534086400, 15, &weights));
+
+        let ranked = super::rank(vec![unknown, image(1534086400, 90.0, 1.0)],
+            1534086400, 15, &weights);
+        assert_eq!(ranked[0].cloud_coverage, Some(90.0));
+    }
+
+    #[test]
+    fn config_policy_filters() {
+        let policy = ConfigPolicy::new(SelectionConfig {
+            sentinel2_window: 5,
+            sentinel2_max_cloud_coverage: Some(30.0),
+            ..SelectionConfig::default()
+        });
+
+        let filter = policy.sentinel2_filter("9xj3e", 1534095541);
+        assert_eq!(filter.geocode.as_deref(), Some("9xj3e"));
+        assert_eq!(filter.platform.as_deref(), Some("Sentinel-2"));
+        assert_eq!(filter.max_cloud_coverage, Some(30.0));
+        assert_eq!(filter.min_pixel_coverage, Some(1.0));
+        assert_eq!(filter.end_timestamp, Some(1534118400));
+        assert_eq!(filter.start_timestamp,
+            Some(1534118400 - 5 * 86400 + 1));
+
+        let filter = policy.modis_filter("9xj3e", 1534095541);
+        assert_eq!(filter.platform.as_deref(), Some("MODIS"));
+        assert_eq!(filter.start_timestamp,
+            Some(1534118400 - 10 * 86400 + 1));
+    }
+
+    #[test]
+    fn config_policy_selection() {
+        let images = vec![
+            image(1534095541, 60.0, 1.0),
+            image(1533922741, 0.0, 1.0),
+            image(1533836341, 10.0, 1.0),
+        ];
+
+        // only images within the tolerance are used directly
+        let policy = ConfigPolicy::new(SelectionConfig::default());
+        let selected = policy.select_stip(1534095541, &images).unwrap();
+        assert_eq!(selected.timestamp, 1534095541);
+
+        let policy = ConfigPolicy::new(SelectionConfig {
+            min_score: Some(0.9),
+            ..SelectionConfig::default()
+        });
+        assert!(policy.select_stip(1534095541, &images).is_none());
+
+        // the best scoring images are imputed in descending time order
+        let modis_images = vec![image(1534095541, 0.0, 1.0)];
+        let (sentinel2_images, _) = policy.select_stitch(1534095541,
+            &images, &modis_images).unwrap();
+        let timestamps: Vec<i64> = sentinel2_images.iter()
+            .map(|x| x.timestamp).collect();
+        assert_eq!(timestamps, vec![1533922741, 1533836341]);
+
+        let policy = ConfigPolicy::new(SelectionConfig {
+            sentinel2_count: 4,
+            ..SelectionConfig::default()
+        });
+        assert!(policy.select_stitch(1534095541,
+            &images, &modis_images).is_none());
+    }
+}